@@ -2,16 +2,41 @@
 
 use anchor_lang::prelude::*;
 use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_lang::solana_program::{keccak, sysvar};
 use anchor_spl::{
   associated_token::AssociatedToken, 
+  token_2022::spl_token_2022::{
+    self,
+    extension::{BaseStateWithExtensions, StateWithExtensions},
+    instruction::AuthorityType,
+  },
   token_interface::
   {
+    approve,
+    Approve,
+    transfer_checked,
+    TransferChecked,
     mint_to, 
     MintTo, Mint, 
+    set_authority,
+    SetAuthority,
+    spl_token_metadata_interface::state::{Field, TokenMetadata},
+    token_group_initialize,
+    TokenGroupInitialize,
+    token_member_initialize,
+    TokenMemberInitialize,
+    token_metadata_initialize,
+    TokenMetadataInitialize,
+    token_metadata_update_field,
+    TokenMetadataUpdateField,
+    Token2022,
     TokenAccount, 
     TokenInterface
   }
 };
+use spl_token_group_interface::state::{TokenGroup, TokenGroupMember};
+use std::mem::size_of;
 
 use switchboard_on_demand::accounts::RandomnessAccountData;
 
@@ -24,12 +49,33 @@ use anchor_spl::metadata::{
   CreateMetadataAccountsV3,
   create_master_edition_v3, 
   CreateMasterEditionV3,
+  update_metadata_accounts_v2,
+  UpdateMetadataAccountsV2,
+  freeze_delegated_account,
+  FreezeDelegatedAccount,
+  thaw_delegated_account,
+  ThawDelegatedAccount,
   set_and_verify_sized_collection_item,
   SetAndVerifySizedCollectionItem,
-  mpl_token_metadata::types::{
-    Creator,
-    CollectionDetails, 
-    DataV2
+  mpl_token_metadata::{
+    types::{
+      Collection,
+      Creator,
+      CollectionDetails, 
+      Data,
+      DataV2,
+      PrintSupply,
+      TokenStandard
+    },
+    instructions::{
+      CreateV1CpiBuilder,
+      MintV1CpiBuilder,
+      UpdateV1CpiBuilder,
+      VerifyCollectionV1CpiBuilder
+    },
+    MAX_NAME_LENGTH,
+    MAX_SYMBOL_LENGTH,
+    MAX_URI_LENGTH
   }
   
 };
@@ -38,13 +84,32 @@ use anchor_spl::metadata::{
 declare_id!("8t2XQzJmVpKjmTokZGcduP2dXXsh6AT4j4bxQeitJCSQ");
 
 #[constant]
-pub const NAME: &str = "Token Lottery Ticket #";
+pub const MAX_SELLER_FEE_BASIS_POINTS: u16 = 10_000;
 
 #[constant]
-pub const SYMBOL: &str = "TLT";
+pub const MAX_REFERRAL_SHARE_BASIS_POINTS: u16 = 10_000;
 
+// about 30 days of 400ms slots
 #[constant]
-pub const URI: &str = "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/metadata.json";
+pub const MAX_SALE_WINDOW_SLOTS: u64 = 6_480_000;
+
+// Metaplex token auth rules program, enforces rule sets on programmable tickets
+pub mod token_auth_rules {
+  use super::*;
+
+  declare_id!("auth9SigNpDKz4sJJ1DfCTuZrZNSAgh9sFD3rboVmgg");
+}
+
+// type + length prefix of every Token-2022 extension entry
+pub const TLV_HEADER_LENGTH: usize = 4;
+
+// longest suffix appended to the ticket base URI: "/" + u64::MAX digits + "-winner.json"
+pub const MAX_TICKET_URI_SUFFIX_LENGTH: usize = 1 + 20 + 12;
+
+pub const MAX_PRICE_TIERS: usize = 8;
+
+#[constant]
+pub const TOKEN_LOTTERY_VERSION: u8 = 1;
 
 #[program]
 pub mod token_lottery {
@@ -54,256 +119,739 @@ pub mod token_lottery {
       ctx: Context<InitializeConfig>,
       start_time: u64,
       end_time: u64,
+      ticket_price: u64,
+      guaranteed_prize: u64
+
+    ) -> Result<()> {
+      let clock = Clock::get()?;
+      let payer = ctx.accounts.payer.key();
+      let sale_config = SaleConfig { start_time, end_time, ticket_price, guaranteed_prize };
+
+      ctx.accounts.token_lottery.initialize(ctx.bumps.token_lottery, payer, clock.slot, &sale_config)?;
+      ctx.accounts.roles.set_inner(Roles { bump: ctx.bumps.roles, operator: payer, treasury: payer });
+
+      escrow_guaranteed_prize(
+        &ctx.accounts.payer,
+        &ctx.accounts.token_lottery.to_account_info(),
+        &ctx.accounts.system_program,
+        guaranteed_prize,
+      )
+
+    }
+
+    // initialize_config and initialize_lottery in one instruction, so tickets can never be
+    // sold against a missing collection and nobody can take the lottery PDA in between
+    pub fn create_lottery(
+      ctx: Context<CreateLottery>,
+      sale_config: SaleConfig,
+      name: String,
+      symbol: String,
+      uri: String,
+      ticket_base_uri: String,
+      ticket_config: TicketConfig
+
+    ) -> Result<()> {
+      require!(
+        ticket_config.ticket_standard != TicketStandard::Token2022,
+        ErrorCode::InvalidTicketStandard
+      );
+
+      let clock = Clock::get()?;
+      let payer = ctx.accounts.payer.key();
+
+      ctx.accounts.token_lottery.initialize(ctx.bumps.token_lottery, payer, clock.slot, &sale_config)?;
+      ctx.accounts.roles.set_inner(Roles { bump: ctx.bumps.roles, operator: payer, treasury: payer });
+      ctx.accounts.token_lottery.advance(LotteryAction::InitializeCollection)?;
+      ctx.accounts.token_lottery.configure_tickets(
+        name.clone(),
+        symbol.clone(),
+        uri.clone(),
+        ticket_base_uri,
+        ticket_config,
+      )?;
+
+      escrow_guaranteed_prize(
+        &ctx.accounts.payer,
+        &ctx.accounts.token_lottery.to_account_info(),
+        &ctx.accounts.system_program,
+        sale_config.guaranteed_prize,
+      )?;
+
+      let signer_seeds: &[&[&[u8]]] = &[&[
+        b"collection_mint".as_ref(),
+        &[ctx.bumps.collection_mint],
+      ]];
+      let seller_fee_basis_points = ctx.accounts.token_lottery.seller_fee_basis_points;
+
+      ctx.accounts.collection_cpi().create_collection(
+        name,
+        symbol,
+        uri,
+        seller_fee_basis_points,
+        signer_seeds,
+      )
+    }
+
+    // fixes the sale window and price before any ticket is sold; presale, pricing and
+    // referral settings have their own setters with the same lock
+    pub fn update_config(
+      ctx: Context<UpdateConfig>,
+      start_time: u64,
+      end_time: u64,
       ticket_price: u64
 
     ) -> Result<()> {
-      ctx.accounts.token_lottery.bump = ctx.bumps.token_lottery;
-      ctx.accounts.token_lottery.start_time = start_time;
-      ctx.accounts.token_lottery.end_time = end_time;
-      ctx.accounts.token_lottery.ticket_price = ticket_price;
-      ctx.accounts.token_lottery.authority = *ctx.accounts.payer.key;
-      ctx.accounts.token_lottery.lottery_pot_amount = 0;
-      ctx.accounts.token_lottery.total_tickets = 0;
-      ctx.accounts.token_lottery.randomness_account = Pubkey::default();
-      ctx.accounts.token_lottery.winner_chosen = false;
-      
+      let clock = Clock::get()?;
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      token_lottery.require_config_unlocked(clock.slot)?;
+      token_lottery.configure_sale(clock.slot, start_time, end_time, ticket_price)?;
+
+      Ok(())
+    }
+
+    // emergency stop for sales, sponsorship and the draw; claims and refunds keep working
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+      ctx.accounts.token_lottery.paused = paused;
+
+      msg!("Lottery paused: {}", paused);
+
+      Ok(())
+    }
+
+    // first half of an authority handover, `None` withdraws a pending proposal
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Option<Pubkey>) -> Result<()> {
+      ctx.accounts.token_lottery.pending_authority = new_authority;
+
+      Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      emit!(RoleChanged {
+        role: Role::Admin,
+        previous: token_lottery.authority,
+        new: ctx.accounts.new_authority.key(),
+      });
+
+      token_lottery.authority = ctx.accounts.new_authority.key();
+      token_lottery.pending_authority = None;
+
+      Ok(())
+    }
+
+    // the admin role is the lottery authority and changes hands through propose/accept
+    pub fn set_role(ctx: Context<SetRole>, role: Role, new: Pubkey) -> Result<()> {
+      let roles = &mut ctx.accounts.roles;
+      let previous = match role {
+        Role::Admin => return err!(ErrorCode::InvalidRole),
+        Role::Operator => std::mem::replace(&mut roles.operator, new),
+        Role::Treasury => std::mem::replace(&mut roles.treasury, new),
+      };
+
+      emit!(RoleChanged { role, previous, new });
+
+      Ok(())
+    }
+
+    pub fn set_presale(
+      ctx: Context<SetPresale>,
+      presale_start_time: u64,
+      merkle_root: [u8; 32],
+      presale_ticket_price: u64,
+      max_tickets_per_wallet: u64
+
+    ) -> Result<()> {
+      let clock = Clock::get()?;
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      token_lottery.require_config_unlocked(clock.slot)?;
+      token_lottery.configure_presale(
+        presale_start_time,
+        merkle_root,
+        presale_ticket_price,
+        max_tickets_per_wallet,
+      )
+    }
+
+    pub fn set_pricing_schedule(
+      ctx: Context<SetPricingSchedule>,
+      pricing_schedule: PricingSchedule
+
+    ) -> Result<()> {
+      let clock = Clock::get()?;
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      token_lottery.require_config_unlocked(clock.slot)?;
+      pricing_schedule.validate()?;
+
+      token_lottery.pricing_schedule = pricing_schedule;
+
+      Ok(())
+    }
+
+    pub fn set_referral_share(ctx: Context<SetReferralShare>, referral_share_bps: u16) -> Result<()> {
+      let clock = Clock::get()?;
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      token_lottery.require_config_unlocked(clock.slot)?;
+      require!(referral_share_bps <= MAX_REFERRAL_SHARE_BASIS_POINTS, ErrorCode::InvalidReferralShare);
+      // a no-loss ticket price is all principal, there is no cut to pass on
+      require!(!token_lottery.no_loss || referral_share_bps == 0, ErrorCode::InvalidReferralShare);
+
+      token_lottery.referral_share_bps = referral_share_bps;
+
       Ok(())
+    }
+
+    // no-loss mode: ticket prices are deposits held in the vault and paid back to ticket holders.
+    // The prize comes from sponsors, the guarantee and anything paid into the vault on top of
+    // the deposits; the program does not put the deposits to work itself
+    pub fn set_no_loss(ctx: Context<SetNoLoss>, no_loss: bool) -> Result<()> {
+      let clock = Clock::get()?;
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      token_lottery.require_config_unlocked(clock.slot)?;
+      require!(!no_loss || token_lottery.referral_share_bps == 0, ErrorCode::InvalidReferralShare);
 
+      token_lottery.no_loss = no_loss;
+      ctx.accounts.vault.bump = ctx.bumps.vault;
+
+      Ok(())
     }
 
-    pub fn initialize_lottery(ctx: Context<InitializeLottery>) -> Result<()> {
+    pub fn initialize_lottery(
+      ctx: Context<InitializeLottery>,
+      name: String,
+      symbol: String,
+      uri: String,
+      ticket_base_uri: String,
+      ticket_config: TicketConfig
+
+    ) -> Result<()> {
+      require!(
+        ticket_config.ticket_standard != TicketStandard::Token2022,
+        ErrorCode::InvalidTicketStandard
+      );
+
+      ctx.accounts.token_lottery.advance(LotteryAction::InitializeCollection)?;
+      ctx.accounts.token_lottery.configure_tickets(
+        name.clone(),
+        symbol.clone(),
+        uri.clone(),
+        ticket_base_uri,
+        ticket_config,
+      )?;
+      let seller_fee_basis_points = ctx.accounts.token_lottery.seller_fee_basis_points;
+
       let signer_seeds: &[&[&[u8]]] = &[&[
           b"collection_mint".as_ref(),
           &[ctx.bumps.collection_mint],
 
       ]];
 
-      msg!("creating Mint account");
+      ctx.accounts.collection_cpi().create_collection(
+        name,
+        symbol,
+        uri,
+        seller_fee_basis_points,
+        signer_seeds,
+      )
+    }
 
-      mint_to(
-        CpiContext::new_with_signer(
-          ctx.accounts.token_program.to_account_info(), 
-          MintTo{
-              mint: ctx.accounts.collection_mint.to_account_info(),
-              to: ctx.accounts.collection_token_account.to_account_info(),
-              authority: ctx.accounts.collection_mint.to_account_info(),
-          },
+    pub fn buy_ticket(ctx: Context<BuyTicket>, proof: Option<Vec<[u8; 32]>>) -> Result<()> {
+      let clock = Clock::get()?;
+      let ticket_name = ticket_name(
+        &ctx.accounts.token_lottery.name,
+        ctx.accounts.token_lottery.total_tickets,
+      );
+
+      require!(
+        ctx.accounts.token_lottery.ticket_standard != TicketStandard::Token2022,
+        ErrorCode::InvalidTicketStandard
+      );
+
+      let ticket_price = ticket_price_for_buyer(
+        &ctx.accounts.token_lottery,
+        clock.slot,
+        &ctx.accounts.payer.key(),
+        proof.as_deref(),
+        ctx.accounts.presale_receipt.as_deref_mut(),
+      )?;
 
-          signer_seeds,
-        ),
-        1 // specify the amount to mint
+      pay_ticket_price(
+        &ctx.accounts.payer,
+        &ticket_funds_account(&ctx.accounts.token_lottery, ctx.accounts.vault.as_ref())?,
+        &ctx.accounts.system_program,
+        ticket_price,
       )?;
 
-      msg!("Creating Metadata account");
+      // Mint ticket
+      let signer_seeds: &[&[&[u8]]] = &[&[
+        b"collection_mint".as_ref(),
+        &[ctx.bumps.collection_mint],
+      ]];
+
+      let token_lottery = &ctx.accounts.token_lottery;
+      let ticket_data = DataV2 {
+        name: ticket_name,
+        symbol: token_lottery.symbol.clone(),
+        uri: ticket_uri(&token_lottery.ticket_base_uri, token_lottery.total_tickets),
+        seller_fee_basis_points: token_lottery.seller_fee_basis_points,
+        creators: ticket_creators(token_lottery, token_lottery.key(), ctx.accounts.roles.treasury),
+        collection: None,
+        uses: None,
+      };
+
+      if token_lottery.ticket_standard == TicketStandard::ProgrammableNonFungible {
+        ctx.accounts.mint_programmable_ticket(ticket_data, signer_seeds)?;
+      } else {
+        ctx.accounts.mint_ticket(ticket_data, signer_seeds)?;
+      }
 
-      create_metadata_accounts_v3(
-        CpiContext::new_with_signer(
+      // soulbound tickets are frozen from the start with the lottery PDA as delegate,
+      // a frozen account cannot revoke it, so only the program can move the ticket
+      if ctx.accounts.token_lottery.freeze_after_close {
+        approve(
+          CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Approve {
+              to: ctx.accounts.destination.to_account_info(),
+              delegate: ctx.accounts.token_lottery.to_account_info(),
+              authority: ctx.accounts.payer.to_account_info(),
+            },
+          ),
+          1,
+        )?;
+
+        freeze_ticket_account(
           ctx.accounts.token_metadata_program.to_account_info(),
-          CreateMetadataAccountsV3 {
-            metadata: ctx.accounts.metadata.to_account_info(),
-            mint: ctx.accounts.collection_mint.to_account_info(),
-            mint_authority: ctx.accounts.collection_mint.to_account_info(),
-            update_authority: ctx.accounts.collection_mint.to_account_info(),
-            payer: ctx.accounts.payer.to_account_info(),
-            system_program: ctx.accounts.system_program.to_account_info(),
-            rent: ctx.accounts.rent.to_account_info(),
-
+          FreezeDelegatedAccount {
+            metadata: ctx.accounts.ticket_metadata.to_account_info(),
+            delegate: ctx.accounts.token_lottery.to_account_info(),
+            token_account: ctx.accounts.destination.to_account_info(),
+            edition: ctx.accounts.ticket_master_edition.to_account_info(),
+            mint: ctx.accounts.ticket_mint.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
           },
+          ctx.accounts.token_lottery.bump,
+        )?;
+      }
 
-          &signer_seeds,
-        ), 
-        DataV2 {
-          name: NAME.to_string(),
-          symbol: SYMBOL.to_string(),
-          uri: URI.to_string(),
-          seller_fee_basis_points: 0,
-          creators: Some(vec! [Creator {
-              address: ctx.accounts.collection_mint.key(),
-              verified: false,
-              share: 100,
-          }]),
-          collection: None,
-          uses: None,
+      let referral_reward = ctx.accounts.pay_referral(ticket_price)?;
+      ctx.accounts.ticket_record.set_inner(TicketRecord {
+        ticket_mint: ctx.accounts.ticket_mint.key(),
+        buyer: ctx.accounts.payer.key(),
+        index: ctx.accounts.token_lottery.total_tickets,
+        price_paid: ticket_price,
+        referral_paid: referral_reward,
+      });
+      ctx.accounts.token_lottery.advance(LotteryAction::SellTicket)?;
+      let ticket_funds = ticket_price
+        .checked_sub(referral_reward)
+        .ok_or(ErrorCode::MathOverflow)?;
+      ctx.accounts.token_lottery.record_ticket_sale(ticket_funds)?;
 
-        }, 
-        true, 
-        true, 
-        Some(CollectionDetails::V1 { size: 0} ) 
+      Ok(())
+
+    }
+
+    // Token-2022 collection: metadata and group data live on the mint itself, no Metaplex accounts
+    pub fn initialize_lottery_token_2022(
+      ctx: Context<InitializeLotteryToken2022>,
+      name: String,
+      symbol: String,
+      uri: String,
+      ticket_base_uri: String,
+      ticket_config: TicketConfig
+
+    ) -> Result<()> {
+      require!(
+        ticket_config.ticket_standard == TicketStandard::Token2022,
+        ErrorCode::InvalidTicketStandard
+      );
+
+      ctx.accounts.token_lottery.advance(LotteryAction::InitializeCollection)?;
+      ctx.accounts.token_lottery.configure_tickets(
+        name.clone(),
+        symbol.clone(),
+        uri.clone(),
+        ticket_base_uri,
+        ticket_config,
       )?;
 
+      let signer_seeds: &[&[&[u8]]] = &[&[
+        b"collection_mint".as_ref(),
+        &[ctx.bumps.collection_mint],
+      ]];
+
+      let collection_mint = ctx.accounts.collection_mint.to_account_info();
+      let metadata = TokenMetadata {
+        update_authority: Some(collection_mint.key()).try_into()?,
+        mint: collection_mint.key(),
+        name: name.clone(),
+        symbol: symbol.clone(),
+        uri: uri.clone(),
+        additional_metadata: vec![],
+      };
+
+      fund_mint_extension(
+        &ctx.accounts.payer,
+        &collection_mint,
+        &ctx.accounts.system_program,
+        metadata.tlv_size_of()? + TLV_HEADER_LENGTH + size_of::<TokenGroup>(),
+      )?;
 
-      msg!("Creating master edition Account");
-      create_master_edition_v3(
+      msg!("Creating collection metadata");
+      token_metadata_initialize(
         CpiContext::new_with_signer(
-          ctx.accounts.token_metadata_program.to_account_info(),
-          CreateMasterEditionV3 {
-            payer: ctx.accounts.payer.to_account_info(),
-            mint: ctx.accounts.collection_mint.to_account_info(),
-            edition: ctx.accounts.master_edition.to_account_info(),
-            mint_authority: ctx.accounts.collection_mint.to_account_info(),
-            update_authority: ctx.accounts.collection_mint.to_account_info(),
-            metadata: ctx.accounts.metadata.to_account_info(),
-            token_program: ctx.accounts.token_program.to_account_info(),
-            system_program: ctx.accounts.system_program.to_account_info(),
-            rent: ctx.accounts.rent.to_account_info()
-
+          ctx.accounts.token_program.to_account_info(),
+          TokenMetadataInitialize {
+            token_program_id: ctx.accounts.token_program.to_account_info(),
+            metadata: collection_mint.clone(),
+            update_authority: collection_mint.clone(),
+            mint_authority: collection_mint.clone(),
+            mint: collection_mint.clone(),
           },
-          &signer_seeds
-          
+          signer_seeds,
         ),
-        Some(0)
+        name,
+        symbol,
+        uri,
       )?;
 
-      msg!("Verifying collection");
-      sign_metadata(
+      msg!("Creating collection group");
+      token_group_initialize(
         CpiContext::new_with_signer(
-          ctx.accounts.token_metadata_program.to_account_info(),
-          SignMetadata {
-            creator: ctx.accounts.collection_mint.to_account_info(),
-            metadata: ctx.accounts.metadata.to_account_info(),
-          }, 
-          signer_seeds
-        ))?;
-
+          ctx.accounts.token_program.to_account_info(),
+          TokenGroupInitialize {
+            token_program_id: ctx.accounts.token_program.to_account_info(),
+            group: collection_mint.clone(),
+            mint: collection_mint.clone(),
+            mint_authority: collection_mint.clone(),
+          },
+          signer_seeds,
+        ),
+        Some(collection_mint.key()),
+        u32::MAX,
+      )?;
 
       Ok(())
     }
 
-    pub fn buy_ticket(ctx: Context<BuyTicket>) -> Result<()> {
-      let clock = Clock::get()?;
-      let ticket_name = NAME.to_owned() + ctx.accounts.token_lottery.total_tickets.to_string().as_str();
-
-      if clock.slot < ctx.accounts.token_lottery.start_time ||
-          clock.slot > ctx.accounts.token_lottery.end_time {
-            return Err(ErrorCode::LotteryNotOpen.into());
-          }
-        
-      system_program::transfer(
-        CpiContext::new(
-          ctx.accounts.system_program.to_account_info(),
-          system_program::Transfer {
-            from: ctx.accounts.payer.to_account_info(),
-            to: ctx.accounts.token_lottery.to_account_info()
-          },
-          
-        ),
-        ctx.accounts.token_lottery.ticket_price,
+    pub fn buy_ticket_token_2022(
+      ctx: Context<BuyTicketToken2022>,
+      proof: Option<Vec<[u8; 32]>>
 
+    ) -> Result<()> {
+      let clock = Clock::get()?;
+      let token_lottery = &ctx.accounts.token_lottery;
+      let ticket_name = ticket_name(&token_lottery.name, token_lottery.total_tickets);
+      let ticket_uri = ticket_uri(&token_lottery.ticket_base_uri, token_lottery.total_tickets);
+
+      require!(
+        token_lottery.ticket_standard == TicketStandard::Token2022,
+        ErrorCode::InvalidTicketStandard
+      );
+
+      let ticket_price = ticket_price_for_buyer(
+        token_lottery,
+        clock.slot,
+        &ctx.accounts.payer.key(),
+        proof.as_deref(),
+        ctx.accounts.presale_receipt.as_deref_mut(),
       )?;
 
-      ctx.accounts.token_lottery.lottery_pot_amount += ctx.accounts.token_lottery.ticket_price;
+      pay_ticket_price(
+        &ctx.accounts.payer,
+        &ticket_funds_account(&ctx.accounts.token_lottery, ctx.accounts.vault.as_ref())?,
+        &ctx.accounts.system_program,
+        ticket_price,
+      )?;
 
-      // Mint ticket
       let signer_seeds: &[&[&[u8]]] = &[&[
         b"collection_mint".as_ref(),
         &[ctx.bumps.collection_mint],
       ]];
 
+      let collection_mint = ctx.accounts.collection_mint.to_account_info();
+      let ticket_mint = ctx.accounts.ticket_mint.to_account_info();
+      let token_program = ctx.accounts.token_program.to_account_info();
+
       mint_to(
         CpiContext::new_with_signer(
-          ctx.accounts.token_program.to_account_info(), 
+          token_program.clone(),
           MintTo {
-            mint: ctx.accounts.ticket_mint.to_account_info(),
+            mint: ticket_mint.clone(),
             to: ctx.accounts.destination.to_account_info(),
-            authority: ctx.accounts.collection_mint.to_account_info(),
-          }, 
-          &signer_seeds,
+            authority: collection_mint.clone(),
+          },
+          signer_seeds,
         ),
         1,
       )?;
 
-      msg!("Create Ticket Metadata account");
+      let metadata = TokenMetadata {
+        update_authority: Some(collection_mint.key()).try_into()?,
+        mint: ticket_mint.key(),
+        name: ticket_name.clone(),
+        symbol: ctx.accounts.token_lottery.symbol.clone(),
+        uri: ticket_uri.clone(),
+        additional_metadata: vec![],
+      };
+
+      fund_mint_extension(
+        &ctx.accounts.payer,
+        &ticket_mint,
+        &ctx.accounts.system_program,
+        metadata.tlv_size_of()? + TLV_HEADER_LENGTH + size_of::<TokenGroupMember>(),
+      )?;
 
-      create_metadata_accounts_v3(
+      msg!("Create Ticket Metadata");
+      token_metadata_initialize(
         CpiContext::new_with_signer(
-          ctx.accounts.token_metadata_program.to_account_info(),
-          CreateMetadataAccountsV3 {
-            metadata: ctx.accounts.ticket_metadata.to_account_info(),
-            mint: ctx.accounts.ticket_mint.to_account_info(),
-            mint_authority: ctx.accounts.collection_mint.to_account_info(),
-            update_authority: ctx.accounts.collection_mint.to_account_info(),
-            payer: ctx.accounts.payer.to_account_info(),
-            system_program: ctx.accounts.system_program.to_account_info(),
-            rent: ctx.accounts.rent.to_account_info(),
-
+          token_program.clone(),
+          TokenMetadataInitialize {
+            token_program_id: token_program.clone(),
+            metadata: ticket_mint.clone(),
+            update_authority: collection_mint.clone(),
+            mint_authority: collection_mint.clone(),
+            mint: ticket_mint.clone(),
           },
+          signer_seeds,
+        ),
+        ticket_name,
+        metadata.symbol,
+        ticket_uri,
+      )?;
 
-          &signer_seeds,
-        ), 
-        DataV2 {
-          name: ticket_name,
-          symbol: SYMBOL.to_string(),
-          uri: URI.to_string(),
-          seller_fee_basis_points: 0,
-          creators: None,
-          collection: None,
-          uses: None,
+      msg!("Adding ticket to collection group");
+      token_member_initialize(
+        CpiContext::new_with_signer(
+          token_program.clone(),
+          TokenMemberInitialize {
+            token_program_id: token_program.clone(),
+            member: ticket_mint.clone(),
+            member_mint: ticket_mint.clone(),
+            member_mint_authority: collection_mint.clone(),
+            group: collection_mint.clone(),
+            group_update_authority: collection_mint.clone(),
+          },
+          signer_seeds,
+        ),
+      )?;
 
-        }, 
-        true, 
-        true, 
+      // fix the supply at one, like a master edition does for Metaplex tickets
+      set_authority(
+        CpiContext::new_with_signer(
+          token_program,
+          SetAuthority {
+            current_authority: collection_mint,
+            account_or_mint: ticket_mint,
+          },
+          signer_seeds,
+        ),
+        AuthorityType::MintTokens,
         None,
       )?;
 
+      let referral_reward = ctx.accounts.pay_referral(ticket_price)?;
+      ctx.accounts.ticket_record.set_inner(TicketRecord {
+        ticket_mint: ctx.accounts.ticket_mint.key(),
+        buyer: ctx.accounts.payer.key(),
+        index: ctx.accounts.token_lottery.total_tickets,
+        price_paid: ticket_price,
+        referral_paid: referral_reward,
+      });
+      ctx.accounts.token_lottery.advance(LotteryAction::SellTicket)?;
+      let ticket_funds = ticket_price
+        .checked_sub(referral_reward)
+        .ok_or(ErrorCode::MathOverflow)?;
+      ctx.accounts.token_lottery.record_ticket_sale(ticket_funds)?;
 
-      msg!("Creating ticket master edition account");
-      create_master_edition_v3(
-        CpiContext::new_with_signer(
-          ctx.accounts.token_metadata_program.to_account_info(),
-          CreateMasterEditionV3 {
-            payer: ctx.accounts.payer.to_account_info(),
-            mint: ctx.accounts.ticket_mint.to_account_info(),
-            edition: ctx.accounts.ticket_master_edition.to_account_info(),
-            mint_authority: ctx.accounts.collection_mint.to_account_info(),
-            update_authority: ctx.accounts.collection_mint.to_account_info(),
-            metadata: ctx.accounts.ticket_metadata.to_account_info(),
-            token_program: ctx.accounts.token_program.to_account_info(),
-            system_program: ctx.accounts.system_program.to_account_info(),
-            rent: ctx.accounts.rent.to_account_info()
+      Ok(())
+    }
+
+    // a referrer opens their earnings account once, before purchases can credit it
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+      ctx.accounts.referrer_earnings.set_inner(ReferrerEarnings {
+        bump: ctx.bumps.referrer_earnings,
+        referrer: ctx.accounts.referrer.key(),
+        unclaimed: 0,
+        total_earned: 0,
+      });
+
+      Ok(())
+    }
+
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+      let unclaimed = ctx.accounts.referrer_earnings.unclaimed;
+
+      transfer_lamports(
+        &ctx.accounts.referrer_earnings.to_account_info(),
+        &ctx.accounts.referrer.to_account_info(),
+        unclaimed,
+      )?;
+
+      ctx.accounts.referrer_earnings.unclaimed = 0;
+
+      Ok(())
+    }
+
+    pub fn fund_pot(ctx: Context<FundPot>, amount: u64) -> Result<()> {
+      let clock = Clock::get()?;
+
+      require!(amount > 0, ErrorCode::InvalidSponsorship);
+      ctx.accounts.token_lottery.require_accepting_funds(clock.slot)?;
 
+      system_program::transfer(
+        CpiContext::new(
+          ctx.accounts.system_program.to_account_info(),
+          system_program::Transfer {
+            from: ctx.accounts.sponsor.to_account_info(),
+            to: ctx.accounts.token_lottery.to_account_info(),
           },
-          &signer_seeds
-          
         ),
-        Some(0),
+        amount,
       )?;
 
-      msg!("Verifying ticket metadata");
-      set_and_verify_sized_collection_item(
-        CpiContext::new_with_signer(
-          ctx.accounts.token_metadata_program.to_account_info(),
-          SetAndVerifySizedCollectionItem {
-            metadata: ctx.accounts.ticket_metadata.to_account_info(),
-            collection_authority: ctx.accounts.collection_mint.to_account_info(),
-            payer: ctx.accounts.payer.to_account_info(),
-            collection_mint: ctx.accounts.collection_mint.to_account_info(),
-            update_authority: ctx.accounts.collection_mint.to_account_info(),
-            collection_metadata: ctx.accounts.collection_metadata.to_account_info(),
-            collection_master_edition: ctx.accounts.collection_master_edition.to_account_info(),
-          },
-          &signer_seeds
-        ), 
-        None
+      let sponsorship = &mut ctx.accounts.sponsorship;
+      sponsorship.sponsor = ctx.accounts.sponsor.key();
+      sponsorship.amount = sponsorship
+        .amount
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+      let token_lottery = &mut ctx.accounts.token_lottery;
+      token_lottery.lottery_pot_amount = token_lottery
+        .lottery_pot_amount
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+      Ok(())
+    }
+
+    // called off before the draw, sponsors and ticket buyers can then take their funds back
+    pub fn cancel_lottery(ctx: Context<CancelLottery>) -> Result<()> {
+      ctx.accounts.token_lottery.advance(LotteryAction::Cancel)
+    }
+
+    pub fn withdraw_sponsorship(ctx: Context<WithdrawSponsorship>) -> Result<()> {
+      ctx.accounts.token_lottery.require_action(LotteryAction::Refund)?;
+
+      let amount = ctx.accounts.sponsorship.amount;
+      ctx.accounts.token_lottery.release_from_pot(amount)?;
+
+      transfer_lamports(
+        &ctx.accounts.token_lottery.to_account_info(),
+        &ctx.accounts.sponsor.to_account_info(),
+        amount,
+      )?;
+
+      Ok(())
+    }
+
+    // a cancelled lottery refunds whoever holds the ticket now, not its first buyer
+    pub fn refund_ticket(ctx: Context<RefundTicket>) -> Result<()> {
+      ctx.accounts.token_lottery.require_action(LotteryAction::Refund)?;
+
+      // no-loss deposits sit in the vault and go back through withdraw_principal
+      require!(!ctx.accounts.token_lottery.no_loss, ErrorCode::NoLossRefund);
+      require!(ctx.accounts.ticket_account.amount > 0, ErrorCode::NoTicket);
+
+      let amount = ctx.accounts.ticket_record.refundable_amount()?;
+      ctx.accounts.token_lottery.release_from_pot(amount)?;
+
+      transfer_lamports(
+        &ctx.accounts.token_lottery.to_account_info(),
+        &ctx.accounts.holder.to_account_info(),
+        amount,
+      )?;
+
+      Ok(())
+    }
+
+    // no-loss mode: whoever holds a ticket once the draw is done, or the lottery is cancelled,
+    // takes its deposit back, the winning ticket included
+    pub fn withdraw_principal(ctx: Context<WithdrawPrincipal>) -> Result<()> {
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      require!(token_lottery.no_loss, ErrorCode::NoLossNotEnabled);
+      token_lottery.require_action(LotteryAction::WithdrawPrincipal)?;
+      require!(ctx.accounts.ticket_account.amount > 0, ErrorCode::NoTicket);
+
+      let amount = ctx.accounts.ticket_record.refundable_amount()?;
+      token_lottery.release_ticket_funds(amount)?;
+
+      transfer_lamports(
+        &ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.holder.to_account_info(),
+        amount,
       )?;
 
-      ctx.accounts.token_lottery.total_tickets += 1;
+      Ok(())
+    }
+
+    // permissionless: moves lamports sent straight to the vault, beyond the deposits, into the pot.
+    // Nothing in the program earns yield, whoever invests the deposits has to pay it in here
+    pub fn harvest_yield(ctx: Context<HarvestYield>) -> Result<()> {
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      require!(token_lottery.no_loss, ErrorCode::NoLossNotEnabled);
+      token_lottery.require_action(LotteryAction::FundPot)?;
+
+      let vault = ctx.accounts.vault.to_account_info();
+      let yield_amount = withdrawable_lamports(&vault)?.saturating_sub(token_lottery.total_deposits);
+
+      msg!("Harvesting vault yield into pot: {}", yield_amount);
+
+      token_lottery.lottery_pot_amount = token_lottery
+        .lottery_pot_amount
+        .checked_add(yield_amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+      transfer_lamports(&vault, &token_lottery.to_account_info(), yield_amount)?;
 
       Ok(())
+    }
+
+    // returns whatever the guarantee did not have to cover once the prize is paid, or all of it
+    // if the lottery was cancelled
+    pub fn reclaim_guarantee(ctx: Context<ReclaimGuarantee>) -> Result<()> {
+      ctx.accounts.token_lottery.require_action(LotteryAction::ReclaimGuarantee)?;
+
+      let amount = ctx.accounts.token_lottery.guaranteed_prize;
+      ctx.accounts.token_lottery.guaranteed_prize = 0;
 
+      transfer_lamports(
+        &ctx.accounts.token_lottery.to_account_info(),
+        &ctx.accounts.authority.to_account_info(),
+        amount,
+      )?;
+
+      Ok(())
     }
 
     pub fn commit_randomness(ctx: Context<CommitRandomness>) -> Result<()> {
       let clock = Clock::get()?;
       let token_lottery = &mut ctx.accounts.token_lottery;
 
-      if ctx.accounts.payer.key() != token_lottery.authority {
+      if ctx.accounts.operator.key() != ctx.accounts.roles.operator {
           return Err(ErrorCode::NotAuthorized.into());
       }
 
+      require!(!token_lottery.paused, ErrorCode::LotteryPaused);
+      token_lottery.close_sales_if_ended(clock.slot)?;
+      token_lottery.advance(LotteryAction::CommitRandomness)?;
+
       let randomness_data = RandomnessAccountData::parse(
-        ctx.accounts.randomness_account_data.data.borrow()).unwrap();
+        ctx.accounts.randomness_account_data.data.borrow())
+        .map_err(|_| ErrorCode::IncorrectRandomnessAccount)?;
 
-      if randomness_data.seed_slot != clock.slot - 1{
+      let previous_slot = clock.slot.checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+
+      if randomness_data.seed_slot != previous_slot {
         return Err(ErrorCode::RandomnessAlreadyRevealed.into());
       }
 
@@ -316,10 +864,12 @@ pub mod token_lottery {
       let clock = Clock::get()?;
       let token_lottery = &mut ctx.accounts.token_lottery;
 
-      if ctx.accounts.payer.key() != token_lottery.authority {
+      if ctx.accounts.operator.key() != ctx.accounts.roles.operator {
         return Err(ErrorCode::NotAuthorized.into());
       }
 
+      require!(!token_lottery.paused, ErrorCode::LotteryPaused);
+
       if ctx.accounts.randomness_account_data.key() != token_lottery.randomness_account {
         return Err(ErrorCode::IncorrectRandomnessAccount.into());
       }
@@ -328,10 +878,11 @@ pub mod token_lottery {
         return Err(ErrorCode::LotteryNotCompleted.into());
       }
 
-      require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+      token_lottery.advance(LotteryAction::ChooseWinner)?;
 
       let randomness_data = RandomnessAccountData::parse(
-        ctx.accounts.randomness_account_data.data.borrow()).unwrap();
+        ctx.accounts.randomness_account_data.data.borrow())
+        .map_err(|_| ErrorCode::IncorrectRandomnessAccount)?;
 
       let reveal_random_value = randomness_data.get_value(&clock)
         .map_err(|_| ErrorCode::RandomnessNotResolved)?;
@@ -339,44 +890,809 @@ pub mod token_lottery {
       msg!("Randomness result: {}", reveal_random_value[0]);
       msg!("Ticket num: {}", token_lottery.total_tickets);
 
-      let winner = reveal_random_value[0] as u64 % token_lottery.total_tickets;
+      let winner = token_lottery.winning_ticket_index(reveal_random_value[0])?;
 
       msg!("Winner: {}", winner);
 
       token_lottery.winner = winner;
-      token_lottery.winner_chosen = true;
 
       Ok(())
       
     }
 
     pub fn claim_prize(ctx: Context<ClaimPrize>) -> Result<()> {
-      require!(ctx.accounts.token_lottery.winner_chosen, ErrorCode::WinnerNotChosen);
+      let collection = ticket_collection(
+        &ctx.accounts.token_lottery,
+        ctx.accounts.ticket_metadata.as_deref(),
+        &ctx.accounts.ticket_mint.to_account_info(),
+      )?;
 
-      require!(ctx.accounts.ticket_metadata.collection.as_ref().unwrap().verified, ErrorCode::NotVerified);
-      require!(ctx.accounts.ticket_metadata.collection.as_ref().unwrap().key == ctx.accounts.collection_mint.key(), ErrorCode::IncorrectTicket);
+      validate_winning_ticket(
+        &ctx.accounts.token_lottery,
+        collection.as_ref(),
+        &ctx.accounts.collection_mint.key(),
+        ctx.accounts.destination.amount,
+      )?;
+      ctx.accounts.token_lottery.require_ticket_frozen(ctx.accounts.destination.is_frozen())?;
 
-      let ticket_name = NAME.to_owned() + &ctx.accounts.token_lottery.winner.to_string();
-      let metadata_name = ctx.accounts.ticket_metadata.name.replace("\u{0}", "");
+      let prize = ctx.accounts.token_lottery.pay_out_prize()?;
 
-      msg!("Ticket name: {}", ticket_name);
-      msg!("Metadata name: {}", metadata_name);
+      transfer_lamports(
+        &ctx.accounts.token_lottery.to_account_info(),
+        &ctx.accounts.payer.to_account_info(),
+        prize,
+      )?;
+
+      Ok(())
+    }
+
+    // moves a soulbound ticket to another holder while sales are open, thawing it
+    // only for the duration of the transfer
+    pub fn transfer_ticket(ctx: Context<TransferTicket>, _ticket_index: u64) -> Result<()> {
+      let clock = Clock::get()?;
+      let token_lottery = &ctx.accounts.token_lottery;
+
+      require!(token_lottery.freeze_after_close, ErrorCode::FreezeNotEnabled);
+      token_lottery.require_action(LotteryAction::TransferTicket)?;
+      require!(clock.slot <= token_lottery.end_time, ErrorCode::LotteryNotOpen);
+
+      let metadata_program = ctx.accounts.token_metadata_program.to_account_info();
+      let ticket_metadata = ctx.accounts.ticket_metadata.to_account_info();
+      let ticket_master_edition = ctx.accounts.ticket_master_edition.to_account_info();
+      let ticket_mint = ctx.accounts.ticket_mint.to_account_info();
+      let token_program = ctx.accounts.token_program.to_account_info();
+      let delegate = token_lottery.to_account_info();
+
+      thaw_ticket_account(
+        metadata_program.clone(),
+        ThawDelegatedAccount {
+          metadata: ticket_metadata.clone(),
+          delegate: delegate.clone(),
+          token_account: ctx.accounts.ticket_account.to_account_info(),
+          edition: ticket_master_edition.clone(),
+          mint: ticket_mint.clone(),
+          token_program: token_program.clone(),
+        },
+        token_lottery.bump,
+      )?;
+
+      transfer_checked(
+        CpiContext::new(
+          token_program.clone(),
+          TransferChecked {
+            from: ctx.accounts.ticket_account.to_account_info(),
+            mint: ticket_mint.clone(),
+            to: ctx.accounts.recipient_ticket_account.to_account_info(),
+            authority: ctx.accounts.holder.to_account_info(),
+          },
+        ),
+        1,
+        0,
+      )?;
+
+      approve(
+        CpiContext::new(
+          token_program.clone(),
+          Approve {
+            to: ctx.accounts.recipient_ticket_account.to_account_info(),
+            delegate: delegate.clone(),
+            authority: ctx.accounts.recipient.to_account_info(),
+          },
+        ),
+        1,
+      )?;
+
+      freeze_ticket_account(
+        metadata_program,
+        FreezeDelegatedAccount {
+          metadata: ticket_metadata,
+          delegate,
+          token_account: ctx.accounts.recipient_ticket_account.to_account_info(),
+          edition: ticket_master_edition,
+          mint: ticket_mint,
+          token_program,
+        },
+        token_lottery.bump,
+      )?;
+
+      Ok(())
+    }
+
+    // permissionless: releases a soulbound ticket once the prize has been paid out or the lottery is cancelled
+    pub fn thaw_ticket(ctx: Context<ThawTicket>, _ticket_index: u64) -> Result<()> {
+      ctx.accounts.token_lottery.require_action(LotteryAction::ThawTicket)?;
+
+      thaw_ticket_account(
+        ctx.accounts.token_metadata_program.to_account_info(),
+        ThawDelegatedAccount {
+          metadata: ctx.accounts.ticket_metadata.to_account_info(),
+          delegate: ctx.accounts.token_lottery.to_account_info(),
+          token_account: ctx.accounts.ticket_account.to_account_info(),
+          edition: ctx.accounts.ticket_master_edition.to_account_info(),
+          mint: ctx.accounts.ticket_mint.to_account_info(),
+          token_program: ctx.accounts.token_program.to_account_info(),
+        },
+        ctx.accounts.token_lottery.bump,
+      )?;
+
+      Ok(())
+    }
+
+    // treasury only: moves secondary-sale royalties paid to the lottery PDA into the pot
+    // while there is still a prize to pay them out with
+    pub fn sweep_royalties(ctx: Context<SweepRoyalties>) -> Result<()> {
+      let token_lottery = &mut ctx.accounts.token_lottery;
+
+      token_lottery.require_action(LotteryAction::SweepRoyalties)?;
+      let royalties = withdrawable_lamports(&token_lottery.to_account_info())?
+        .saturating_sub(token_lottery.lottery_pot_amount)
+        .saturating_sub(token_lottery.guaranteed_prize);
+
+      msg!("Sweeping royalties into pot: {}", royalties);
+
+      token_lottery.lottery_pot_amount = token_lottery
+        .lottery_pot_amount
+        .checked_add(royalties)
+        .ok_or(ErrorCode::MathOverflow)?;
+      token_lottery.swept_royalties = token_lottery
+        .swept_royalties
+        .checked_add(royalties)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+      Ok(())
+    }
+
+    // treasury only: once the lottery is settled, pays out royalties no prize or refund can use,
+    // whether they were swept into the pot or arrived later from resales
+    pub fn withdraw_royalties(ctx: Context<WithdrawRoyalties>) -> Result<()> {
+      let token_lottery = &mut ctx.accounts.token_lottery;
+      let withdrawable = withdrawable_lamports(&token_lottery.to_account_info())?;
+      let royalties = token_lottery.take_leftover_royalties(withdrawable)?;
+
+      msg!("Withdrawing royalties to treasury: {}", royalties);
+
+      transfer_lamports(
+        &token_lottery.to_account_info(),
+        &ctx.accounts.treasury.to_account_info(),
+        royalties,
+      )?;
+
+      Ok(())
+    }
+
+    // permissionless: points the winning ticket's metadata at its winner URI
+    pub fn update_ticket_status(ctx: Context<UpdateTicketStatus>) -> Result<()> {
+      ctx.accounts.token_lottery.require_action(LotteryAction::MarkWinner)?;
+
+      let signer_seeds: &[&[&[u8]]] = &[&[
+        b"collection_mint".as_ref(),
+        &[ctx.bumps.collection_mint],
+      ]];
+
+      let token_lottery = &ctx.accounts.token_lottery;
+      let uri = winning_ticket_uri(&token_lottery.ticket_base_uri, token_lottery.winner);
+
+      msg!("Marking winning ticket: {}", uri);
+
+      if token_lottery.ticket_standard == TicketStandard::Token2022 {
+        let ticket_mint = ctx.accounts.ticket_mint.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+
+        fund_mint_extension(
+          &ctx.accounts.payer,
+          &ticket_mint,
+          &ctx.accounts.system_program,
+          uri.len() - ticket_uri(&token_lottery.ticket_base_uri, token_lottery.winner).len(),
+        )?;
+
+        token_metadata_update_field(
+          CpiContext::new_with_signer(
+            token_program.clone(),
+            TokenMetadataUpdateField {
+              token_program_id: token_program,
+              metadata: ticket_mint,
+              update_authority: ctx.accounts.collection_mint.to_account_info(),
+            },
+            signer_seeds,
+          ),
+          Field::Uri,
+          uri,
+        )?;
+
+        return Ok(());
+      }
+
+      let ticket_metadata = ctx.accounts.ticket_metadata.as_ref().ok_or(ErrorCode::NotVerified)?;
+
+      // Token Metadata rejects the legacy update instruction for programmable NFTs
+      if token_lottery.ticket_standard == TicketStandard::ProgrammableNonFungible {
+        let ticket_master_edition = ctx.accounts.ticket_master_edition.as_ref().ok_or(ErrorCode::MissingProgrammableAccounts)?;
+        let sysvar_instructions = ctx.accounts.sysvar_instructions.as_ref().ok_or(ErrorCode::MissingProgrammableAccounts)?;
+
+        let metadata_program = ctx.accounts.token_metadata_program.to_account_info();
+        let collection_mint = ctx.accounts.collection_mint.to_account_info();
+        let ticket_mint = ctx.accounts.ticket_mint.to_account_info();
+        let metadata = ticket_metadata.to_account_info();
+        let edition = ticket_master_edition.to_account_info();
+        let payer = ctx.accounts.payer.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let sysvar_instructions = sysvar_instructions.to_account_info();
+
+        UpdateV1CpiBuilder::new(&metadata_program)
+          .authority(&collection_mint)
+          .mint(&ticket_mint)
+          .metadata(&metadata)
+          .edition(Some(&edition))
+          .payer(&payer)
+          .system_program(&system_program)
+          .sysvar_instructions(&sysvar_instructions)
+          .data(Data {
+            name: ticket_metadata.name.replace("\u{0}", ""),
+            symbol: ticket_metadata.symbol.replace("\u{0}", ""),
+            uri,
+            seller_fee_basis_points: ticket_metadata.seller_fee_basis_points,
+            creators: ticket_metadata.creators.clone(),
+          })
+          .invoke_signed(signer_seeds)?;
+
+        return Ok(());
+      }
+
+      update_metadata_accounts_v2(
+        CpiContext::new_with_signer(
+          ctx.accounts.token_metadata_program.to_account_info(),
+          UpdateMetadataAccountsV2 {
+            metadata: ticket_metadata.to_account_info(),
+            update_authority: ctx.accounts.collection_mint.to_account_info(),
+          },
+          signer_seeds,
+        ),
+        None,
+        Some(DataV2 {
+          name: ticket_metadata.name.replace("\u{0}", ""),
+          symbol: ticket_metadata.symbol.replace("\u{0}", ""),
+          uri,
+          seller_fee_basis_points: ticket_metadata.seller_fee_basis_points,
+          creators: ticket_metadata.creators.clone(),
+          collection: ticket_metadata.collection.clone(),
+          uses: ticket_metadata.uses.clone(),
+        }),
+        None,
+        None,
+      )?;
+
+      Ok(())
+    }
+
+    // permissionless: pays the pot to whoever currently holds the winning ticket
+    pub fn settle_prize(ctx: Context<SettlePrize>) -> Result<()> {
+      let collection = ticket_collection(
+        &ctx.accounts.token_lottery,
+        ctx.accounts.ticket_metadata.as_deref(),
+        &ctx.accounts.ticket_mint.to_account_info(),
+      )?;
+
+      validate_winning_ticket(
+        &ctx.accounts.token_lottery,
+        collection.as_ref(),
+        &ctx.accounts.collection_mint.key(),
+        ctx.accounts.ticket_account.amount,
+      )?;
+      ctx.accounts.token_lottery.require_ticket_frozen(ctx.accounts.ticket_account.is_frozen())?;
+
+      msg!("Settling prize to ticket owner: {}", ctx.accounts.winner.key());
+
+      let prize = ctx.accounts.token_lottery.pay_out_prize()?;
+
+      transfer_lamports(
+        &ctx.accounts.token_lottery.to_account_info(),
+        &ctx.accounts.winner.to_account_info(),
+        prize,
+      )?;
+
+      Ok(())
+    }
 
-      require!(metadata_name == ticket_name, ErrorCode::IncorrectTicket);
-      require!(ctx.accounts.destination.amount > 0, ErrorCode::NoTicket);
+    // moves a lottery created before accounts were versioned onto the current layout
+    pub fn migrate_lottery(ctx: Context<MigrateLottery>, ticket_base_uri: String) -> Result<()> {
+      let token_lottery = ctx.accounts.token_lottery.to_account_info();
+      let payer = ctx.accounts.payer.key();
 
-      **ctx.accounts.token_lottery.to_account_info().try_borrow_mut_lamports()? -= ctx.accounts.token_lottery.lottery_pot_amount;
-      **ctx.accounts.payer.try_borrow_mut_lamports()? += ctx.accounts.token_lottery.lottery_pot_amount;
+      let migrated = TokenLottery::from_v0(
+        &token_lottery.try_borrow_data()?,
+        !ctx.accounts.collection_mint.data_is_empty(),
+        ticket_base_uri,
+      )?;
+
+      require!(migrated.authority == payer, ErrorCode::NotAuthorized);
+
+      let new_len = 8 + TokenLottery::INIT_SPACE;
+      let rent_due = migrated.migration_rent_due(&Rent::get()?, token_lottery.lamports());
+
+      if rent_due > 0 {
+        system_program::transfer(
+          CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+              from: ctx.accounts.payer.to_account_info(),
+              to: token_lottery.clone(),
+            },
+          ),
+          rent_due,
+        )?;
+      }
+
+      token_lottery.realloc(new_len, true)?;
+      migrated.try_serialize(&mut &mut token_lottery.try_borrow_mut_data()?[..])?;
 
-      ctx.accounts.token_lottery.lottery_pot_amount = 0;
+      // v0 lotteries had a single key, it keeps every role
+      ctx.accounts.roles.set_inner(Roles { bump: ctx.bumps.roles, operator: payer, treasury: payer });
 
       Ok(())
     }
 
+}
+
+// secondary-sale royalty recipients of a ticket: the treasury and the pot PDA,
+// split by `pot_royalty_share`. Marketplaces only pay royalties to listed creators.
+// Royalties paid to the PDA are swept into the pot before the draw and can be
+// withdrawn by the treasury once the prize is settled, so none are stranded.
+pub fn ticket_creators(
+  token_lottery: &TokenLottery,
+  token_lottery_key: Pubkey,
+  treasury: Pubkey,
+) -> Option<Vec<Creator>> {
+  if token_lottery.seller_fee_basis_points == 0 {
+    return None;
+  }
+
+  let creators = [
+    (treasury, 100 - token_lottery.pot_royalty_share),
+    (token_lottery_key, token_lottery.pot_royalty_share),
+  ]
+  .into_iter()
+  .filter(|(_, share)| *share > 0)
+  .map(|(address, share)| Creator { address, verified: false, share })
+  .collect();
+
+  Some(creators)
+}
+
+// ticket name as `{prefix}{index}`, shortening the prefix so the result always
+// fits the Metaplex name limit
+pub fn ticket_name(prefix: &str, index: u64) -> String {
+  let index = index.to_string();
+  let mut prefix_len = prefix.len().min(MAX_NAME_LENGTH.saturating_sub(index.len()));
+
+  while !prefix.is_char_boundary(prefix_len) {
+    prefix_len -= 1;
+  }
+
+  format!("{}{}", &prefix[..prefix_len], index)
+}
+
+// metadata URI of a single ticket, e.g. `{base}/42.json`
+pub fn ticket_uri(base_uri: &str, index: u64) -> String {
+  format!("{}/{}.json", base_uri, index)
+}
+
+// metadata URI the winning ticket is switched to after the draw
+pub fn winning_ticket_uri(base_uri: &str, index: u64) -> String {
+  format!("{}/{}-winner.json", base_uri, index)
+}
+
+// collection a ticket belongs to: the Metaplex collection for metadata-program
+// tickets, the TokenGroupMember extension for Token-2022 tickets
+pub fn ticket_collection(
+  token_lottery: &TokenLottery,
+  ticket_metadata: Option<&MetadataAccount>,
+  ticket_mint: &AccountInfo,
+) -> Result<Option<Collection>> {
+  if token_lottery.ticket_standard != TicketStandard::Token2022 {
+    return Ok(ticket_metadata.ok_or(ErrorCode::NotVerified)?.collection.clone());
+  }
+
+  let mint_data = ticket_mint.try_borrow_data()?;
+  let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+  Ok(mint
+    .get_extension::<TokenGroupMember>()
+    .ok()
+    .map(|member| Collection { verified: true, key: member.group }))
+}
+
+// checks shared by every instruction that pays out on the winning ticket.
+// The ticket itself is identified by its mint, which the accounts struct derives
+// from `token_lottery.winner`, so the metadata name is never compared.
+pub fn validate_winning_ticket(
+  token_lottery: &TokenLottery,
+  ticket_collection: Option<&Collection>,
+  collection_mint: &Pubkey,
+  ticket_amount: u64,
+) -> Result<()> {
+  require!(token_lottery.winner_drawn(), ErrorCode::WinnerNotChosen);
+
+  let collection = ticket_collection.ok_or(ErrorCode::NotVerified)?;
+
+  require!(collection.verified, ErrorCode::NotVerified);
+  require!(collection.key == *collection_mint, ErrorCode::IncorrectTicket);
+  require!(ticket_amount > 0, ErrorCode::NoTicket);
+
+  Ok(())
+}
+
+// keccak merkle proof over sorted pairs, leaves are `keccak(wallet)`
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+  let computed = proof.iter().fold(leaf, |node, sibling| {
+    if node <= *sibling {
+      keccak::hashv(&[&node, sibling]).to_bytes()
+    } else {
+      keccak::hashv(&[sibling, &node]).to_bytes()
+    }
+  });
+
+  computed == root
+}
+
+// price the buyer pays at `slot`: the presale price for allowlisted wallets during
+// the presale window, the regular price once sales open
+pub fn ticket_price_for_buyer(
+  token_lottery: &TokenLottery,
+  slot: u64,
+  buyer: &Pubkey,
+  proof: Option<&[[u8; 32]]>,
+  presale_receipt: Option<&mut PresaleReceipt>,
+) -> Result<u64> {
+  require!(!token_lottery.paused, ErrorCode::LotteryPaused);
+
+  if !token_lottery.in_presale(slot) {
+    token_lottery.require_open(slot)?;
+    return token_lottery.current_ticket_price(slot);
+  }
+
+  let proof = proof.ok_or(ErrorCode::NotAllowlisted)?;
+  let presale_receipt = presale_receipt.ok_or(ErrorCode::NotAllowlisted)?;
+  let leaf = keccak::hash(buyer.as_ref()).to_bytes();
+
+  require!(
+    verify_merkle_proof(proof, token_lottery.presale_merkle_root, leaf),
+    ErrorCode::NotAllowlisted
+  );
+
+  let tickets_bought = presale_receipt
+    .tickets_bought
+    .checked_add(1)
+    .ok_or(ErrorCode::MathOverflow)?;
+
+  require!(
+    tickets_bought <= token_lottery.presale_max_per_wallet,
+    ErrorCode::PresaleLimitReached
+  );
+
+  presale_receipt.buyer = *buyer;
+  presale_receipt.tickets_bought = tickets_bought;
+
+  Ok(token_lottery.presale_ticket_price)
+}
+
+// referrer's cut of a ticket price, rounded down
+pub fn referral_reward(ticket_price: u64, referral_share_bps: u16) -> Result<u64> {
+  let reward = (ticket_price as u128)
+    .checked_mul(referral_share_bps as u128)
+    .ok_or(ErrorCode::MathOverflow)?
+    / MAX_REFERRAL_SHARE_BASIS_POINTS as u128;
+
+  u64::try_from(reward).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+// referrer of a purchase, the referrer and their earnings account have to come together
+pub fn referred_by(
+  buyer: &Pubkey,
+  referrer: Option<Pubkey>,
+  earnings_referrer: Option<Pubkey>,
+) -> Result<Option<Pubkey>> {
+  let referrer = match (referrer, earnings_referrer) {
+    (Some(referrer), Some(earnings_referrer)) => {
+      require!(referrer == earnings_referrer, ErrorCode::IncorrectReferrer);
+      referrer
+    }
+    (None, None) => return Ok(None),
+    _ => return err!(ErrorCode::MissingReferralAccounts),
+  };
+
+  require!(referrer != *buyer, ErrorCode::SelfReferral);
 
+  Ok(Some(referrer))
+}
+
+// moves the referrer's cut of a ticket price from the pot into their earnings account
+pub fn credit_referrer<'info>(
+  payer: &Signer<'info>,
+  token_lottery: &Account<'info, TokenLottery>,
+  referrer: Option<&UncheckedAccount<'info>>,
+  referrer_earnings: Option<&mut Account<'info, ReferrerEarnings>>,
+  ticket_price: u64,
+) -> Result<u64> {
+  referred_by(
+    &payer.key(),
+    referrer.map(|referrer| referrer.key()),
+    referrer_earnings.as_ref().map(|referrer_earnings| referrer_earnings.referrer),
+  )?;
+
+  let Some(referrer_earnings) = referrer_earnings else {
+    return Ok(0);
+  };
+
+  let reward = referral_reward(ticket_price, token_lottery.referral_share_bps)?;
+
+  transfer_lamports(
+    &token_lottery.to_account_info(),
+    &referrer_earnings.to_account_info(),
+    reward,
+  )?;
+
+  referrer_earnings.unclaimed = referrer_earnings
+    .unclaimed
+    .checked_add(reward)
+    .ok_or(ErrorCode::MathOverflow)?;
+  referrer_earnings.total_earned = referrer_earnings
+    .total_earned
+    .checked_add(reward)
+    .ok_or(ErrorCode::MathOverflow)?;
+
+  Ok(reward)
+}
+
+// account ticket payments go to and are refunded from: the vault in no-loss mode, the pot otherwise
+pub fn ticket_funds_account<'info>(
+  token_lottery: &Account<'info, TokenLottery>,
+  vault: Option<&Account<'info, Vault>>,
+) -> Result<AccountInfo<'info>> {
+  if !token_lottery.no_loss {
+    return Ok(token_lottery.to_account_info());
+  }
+
+  vault
+    .map(|vault| vault.to_account_info())
+    .ok_or(ErrorCode::MissingVault.into())
+}
+
+// transfers the ticket price from the buyer into the account holding ticket payments
+pub fn pay_ticket_price<'info>(
+  payer: &Signer<'info>,
+  recipient: &AccountInfo<'info>,
+  system_program: &Program<'info, System>,
+  ticket_price: u64,
+) -> Result<()> {
+  system_program::transfer(
+    CpiContext::new(
+      system_program.to_account_info(),
+      system_program::Transfer {
+        from: payer.to_account_info(),
+        to: recipient.clone()
+      },
+    ),
+    ticket_price,
+  )
+}
+
+// Token-2022 reallocs a mint when metadata or group data is written to it,
+// but the extra rent has to be there beforehand
+pub fn fund_mint_extension<'info>(
+  payer: &Signer<'info>,
+  mint: &AccountInfo<'info>,
+  system_program: &Program<'info, System>,
+  extra_len: usize,
+) -> Result<()> {
+  let required = Rent::get()?.minimum_balance(mint.data_len() + extra_len);
+  let lamports = required.saturating_sub(mint.lamports());
+
+  if lamports > 0 {
+    system_program::transfer(
+      CpiContext::new(
+        system_program.to_account_info(),
+        system_program::Transfer {
+          from: payer.to_account_info(),
+          to: mint.clone(),
+        },
+      ),
+      lamports,
+    )?;
+  }
+
+  Ok(())
+}
+
+// lamports held by a program-owned account above its rent-exempt minimum
+pub fn withdrawable_lamports(account: &AccountInfo) -> Result<u64> {
+  let rent_exempt_minimum = Rent::get()?.minimum_balance(account.data_len());
+
+  account
+    .lamports()
+    .checked_sub(rent_exempt_minimum)
+    .ok_or(ErrorCode::InsufficientFunds.into())
+}
 
+// the guarantee is escrowed up front in the lottery account, outside the pot
+pub fn escrow_guaranteed_prize<'info>(
+  payer: &Signer<'info>,
+  token_lottery: &AccountInfo<'info>,
+  system_program: &Program<'info, System>,
+  amount: u64,
+) -> Result<()> {
+  if amount == 0 {
+    return Ok(());
+  }
+
+  system_program::transfer(
+    CpiContext::new(
+      system_program.to_account_info(),
+      system_program::Transfer {
+        from: payer.to_account_info(),
+        to: token_lottery.clone(),
+      },
+    ),
+    amount,
+  )
+}
+
+// accounts that create the Metaplex collection, shared by initialize_lottery and create_lottery
+pub struct CollectionCpi<'info> {
+  pub payer: AccountInfo<'info>,
+  pub collection_mint: AccountInfo<'info>,
+  pub collection_token_account: AccountInfo<'info>,
+  pub metadata: AccountInfo<'info>,
+  pub master_edition: AccountInfo<'info>,
+  pub token_metadata_program: AccountInfo<'info>,
+  pub token_program: AccountInfo<'info>,
+  pub system_program: AccountInfo<'info>,
+  pub rent: AccountInfo<'info>,
+}
+
+impl<'info> CollectionCpi<'info> {
+  pub fn create_collection(
+    &self,
+    name: String,
+    symbol: String,
+    uri: String,
+    seller_fee_basis_points: u16,
+    signer_seeds: &[&[&[u8]]],
+  ) -> Result<()> {
+    msg!("creating Mint account");
+
+    mint_to(
+      CpiContext::new_with_signer(
+        self.token_program.clone(), 
+        MintTo{
+            mint: self.collection_mint.clone(),
+            to: self.collection_token_account.clone(),
+            authority: self.collection_mint.clone(),
+        },
+
+        signer_seeds,
+      ),
+      1 // specify the amount to mint
+    )?;
+
+    msg!("Creating Metadata account");
+
+    create_metadata_accounts_v3(
+      CpiContext::new_with_signer(
+        self.token_metadata_program.clone(),
+        CreateMetadataAccountsV3 {
+          metadata: self.metadata.clone(),
+          mint: self.collection_mint.clone(),
+          mint_authority: self.collection_mint.clone(),
+          update_authority: self.collection_mint.clone(),
+          payer: self.payer.clone(),
+          system_program: self.system_program.clone(),
+          rent: self.rent.clone(),
+
+        },
+
+        signer_seeds,
+      ), 
+      DataV2 {
+        name,
+        symbol,
+        uri,
+        seller_fee_basis_points,
+        creators: Some(vec! [Creator {
+            address: self.collection_mint.key(),
+            verified: false,
+            share: 100,
+        }]),
+        collection: None,
+        uses: None,
+
+      }, 
+      true, 
+      true, 
+      Some(CollectionDetails::V1 { size: 0} ) 
+    )?;
+
+
+    msg!("Creating master edition Account");
+    create_master_edition_v3(
+      CpiContext::new_with_signer(
+        self.token_metadata_program.clone(),
+        CreateMasterEditionV3 {
+          payer: self.payer.clone(),
+          mint: self.collection_mint.clone(),
+          edition: self.master_edition.clone(),
+          mint_authority: self.collection_mint.clone(),
+          update_authority: self.collection_mint.clone(),
+          metadata: self.metadata.clone(),
+          token_program: self.token_program.clone(),
+          system_program: self.system_program.clone(),
+          rent: self.rent.clone()
+
+        },
+        signer_seeds
+        
+      ),
+      Some(0)
+    )?;
+
+    msg!("Verifying collection");
+    sign_metadata(
+      CpiContext::new_with_signer(
+        self.token_metadata_program.clone(),
+        SignMetadata {
+          creator: self.collection_mint.clone(),
+          metadata: self.metadata.clone(),
+        }, 
+        signer_seeds
+      ))?;
+
+    Ok(())
+  }
+}
+
+// move lamports out of a program-owned account (pot, refunds, fees, payouts)
+// without ever leaving it below the rent-exempt minimum
+pub fn transfer_lamports<'info>(
+  from: &AccountInfo<'info>,
+  to: &AccountInfo<'info>,
+  amount: u64,
+) -> Result<()> {
+  require!(amount <= withdrawable_lamports(from)?, ErrorCode::InsufficientFunds);
+
+  let from_lamports = from
+    .lamports()
+    .checked_sub(amount)
+    .ok_or(ErrorCode::InsufficientFunds)?;
+  let to_lamports = to
+    .lamports()
+    .checked_add(amount)
+    .ok_or(ErrorCode::MathOverflow)?;
+
+  **from.try_borrow_mut_lamports()? = from_lamports;
+  **to.try_borrow_mut_lamports()? = to_lamports;
+
+  Ok(())
 }
 
+// soulbound tickets are delegated to the lottery PDA, which signs their freeze and thaw
+pub fn freeze_ticket_account<'info>(
+  token_metadata_program: AccountInfo<'info>,
+  accounts: FreezeDelegatedAccount<'info>,
+  lottery_bump: u8,
+) -> Result<()> {
+  let signer_seeds: &[&[&[u8]]] = &[&[b"token_lottery".as_ref(), &[lottery_bump]]];
+
+  freeze_delegated_account(CpiContext::new_with_signer(token_metadata_program, accounts, signer_seeds))
+}
+
+pub fn thaw_ticket_account<'info>(
+  token_metadata_program: AccountInfo<'info>,
+  accounts: ThawDelegatedAccount<'info>,
+  lottery_bump: u8,
+) -> Result<()> {
+  let signer_seeds: &[&[&[u8]]] = &[&[b"token_lottery".as_ref(), &[lottery_bump]]];
+
+  thaw_delegated_account(CpiContext::new_with_signer(token_metadata_program, accounts, signer_seeds))
+}
 
 // define the initialize_lottery account 
 #[derive(Accounts)]
@@ -396,6 +1712,16 @@ pub struct InitializeConfig<'info> {
 
   pub token_lottery: Account<'info, TokenLottery>,
 
+  #[account(
+    init,
+    payer = payer,
+    space = 8 + Roles::INIT_SPACE,
+    seeds = [b"roles".as_ref()],
+    bump
+  )]
+
+  pub roles: Account<'info, Roles>,
+
   pub system_program: Program<'info, System>
 
 }
@@ -405,7 +1731,18 @@ pub struct InitializeConfig<'info> {
 pub struct InitializeLottery<'info> {
 
   #[account(mut)]
-  pub payer: Signer<'info>,
+  pub payer: Signer<'info>, // pays rent, does not have to be the authority
+
+  pub authority: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
 
 
   #[account(
@@ -470,57 +1807,72 @@ pub struct InitializeLottery<'info> {
 
 }
 
+impl<'info> InitializeLottery<'info> {
+  pub fn collection_cpi(&self) -> CollectionCpi<'info> {
+    CollectionCpi {
+      payer: self.payer.to_account_info(),
+      collection_mint: self.collection_mint.to_account_info(),
+      collection_token_account: self.collection_token_account.to_account_info(),
+      metadata: self.metadata.to_account_info(),
+      master_edition: self.master_edition.to_account_info(),
+      token_metadata_program: self.token_metadata_program.to_account_info(),
+      token_program: self.token_program.to_account_info(),
+      system_program: self.system_program.to_account_info(),
+      rent: self.rent.to_account_info(),
+    }
+  }
+}
+
 #[derive(Accounts)]
-pub struct BuyTicket<'info> {
-  
+pub struct CreateLottery<'info> {
+
   #[account(mut)]
-  pub payer: Signer<'info>, //buyer
+  pub payer: Signer<'info>,
 
   #[account(
-    mut,
+    init,
+    payer = payer,
+    space = 8 + TokenLottery::INIT_SPACE,
     seeds = [b"token_lottery".as_ref()],
-    bump = token_lottery.bump
+    bump
   )]
 
-  pub token_lottery: Account<'info, TokenLottery>,
+  pub token_lottery: Box<Account<'info, TokenLottery>>,
 
   #[account(
-    mut,
-    seeds = [b"collection_mint".as_ref()],
+    init,
+    payer = payer,
+    space = 8 + Roles::INIT_SPACE,
+    seeds = [b"roles".as_ref()],
     bump
-
   )]
 
-  pub collection_mint: InterfaceAccount<'info, Mint>,
+  pub roles: Box<Account<'info, Roles>>,
+
 
   #[account(
-    mut, 
-    seeds = [
-      b"metadata", 
-      token_metadata_program.key().as_ref(),
-      ticket_mint.key().as_ref()
-      ],
-    bump,
-    seeds::program = token_metadata_program.key(),
+    init,
+    payer = payer,
+    mint::decimals = 0,
+    mint::authority = collection_mint,
+    mint::freeze_authority = collection_mint,
+    seeds = [b"collection_mint".as_ref()],
+    bump
   )]
 
-  /// CHECK: This account is checked by the metadata smart contract
-  pub ticket_metadata: UncheckedAccount<'info>,
+  pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
 
   #[account(
-    mut,
-    seeds = [
-      b"metadata", 
-      token_metadata_program.key().as_ref(),
-      ticket_mint.key().as_ref(),
-      b"edition"
-      ],
-    bump,
-    seeds::program = token_metadata_program.key(),
+    init,
+    payer = payer,
+    token::mint = collection_mint,
+    token::authority = collection_token_account,
+    seeds = [b"collection_associated_token".as_ref()],
+    bump
+
   )]
 
-  /// CHECK: This account is checked by the metadata smart contract
-  pub ticket_master_edition: UncheckedAccount<'info>,
+  pub collection_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
   #[account(
     mut, 
@@ -534,7 +1886,7 @@ pub struct BuyTicket<'info> {
   )]
 
   /// CHECK: This account is checked by the metadata smart contract
-  pub collection_metadata: UncheckedAccount<'info>,
+  pub metadata: UncheckedAccount<'info>,
 
   #[account(
     mut,
@@ -549,20 +1901,133 @@ pub struct BuyTicket<'info> {
   )]
 
   /// CHECK: This account is checked by the metadata smart contract
-  pub collection_master_edition: UncheckedAccount<'info>,
+  pub master_edition: UncheckedAccount<'info>,
 
-  #[account(
-    init,
-    payer = payer,
-    mint::decimals = 0,
-    mint::authority = collection_mint,
-    mint::freeze_authority = collection_mint,
-    mint::token_program = token_program,
-    seeds = [token_lottery.total_tickets.to_le_bytes().as_ref()],
-    bump
-  )]
+  pub token_metadata_program: Program<'info, Metadata>,
+  pub associated_token_program: Program<'info, AssociatedToken>,
+  pub token_program: Interface<'info, TokenInterface>,
+  pub system_program: Program<'info, System>,
 
-  pub ticket_mint: InterfaceAccount<'info, Mint>,
+  pub rent: Sysvar<'info, Rent>,
+
+}
+
+impl<'info> CreateLottery<'info> {
+  pub fn collection_cpi(&self) -> CollectionCpi<'info> {
+    CollectionCpi {
+      payer: self.payer.to_account_info(),
+      collection_mint: self.collection_mint.to_account_info(),
+      collection_token_account: self.collection_token_account.to_account_info(),
+      metadata: self.metadata.to_account_info(),
+      master_edition: self.master_edition.to_account_info(),
+      token_metadata_program: self.token_metadata_program.to_account_info(),
+      token_program: self.token_program.to_account_info(),
+      system_program: self.system_program.to_account_info(),
+      rent: self.rent.to_account_info(),
+    }
+  }
+}
+
+#[derive(Accounts)]
+pub struct BuyTicket<'info> {
+  
+  #[account(mut)]
+  pub payer: Signer<'info>, //buyer
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [b"roles".as_ref()],
+    bump = roles.bump,
+  )]
+
+  pub roles: Account<'info, Roles>,
+
+  #[account(
+    mut,
+    seeds = [b"collection_mint".as_ref()],
+    bump
+
+  )]
+
+  pub collection_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    mut, 
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub ticket_metadata: UncheckedAccount<'info>,
+
+  #[account(
+    mut,
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref(),
+      b"edition"
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub ticket_master_edition: UncheckedAccount<'info>,
+
+  #[account(
+    mut, 
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      collection_mint.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub collection_metadata: UncheckedAccount<'info>,
+
+  #[account(
+    mut,
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      collection_mint.key().as_ref(),
+      b"edition"
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub collection_master_edition: UncheckedAccount<'info>,
+
+  #[account(
+    init,
+    payer = payer,
+    mint::decimals = 0,
+    mint::authority = collection_mint,
+    mint::freeze_authority = collection_mint,
+    mint::token_program = token_program,
+    seeds = [token_lottery.total_tickets.to_le_bytes().as_ref()],
+    bump
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
 
   #[account(
     init,
@@ -574,133 +2039,1933 @@ pub struct BuyTicket<'info> {
 
   pub destination: InterfaceAccount<'info, TokenAccount>, 
 
+  #[account(
+    init,
+    payer = payer,
+    space = 8 + TicketRecord::INIT_SPACE,
+    seeds = [b"ticket_record".as_ref(), ticket_mint.key().as_ref()],
+    bump
+  )]
+
+  pub ticket_record: Account<'info, TicketRecord>,
+
   pub associated_token_program: Program<'info, AssociatedToken>,
   pub token_program: Interface<'info, TokenInterface>,
   pub token_metadata_program: Program<'info, Metadata>,
   pub system_program: Program<'info, System>,
-  pub rent: Sysvar<'info, Rent>
+  pub rent: Sysvar<'info, Rent>,
+
+  // presale purchases only
+
+  #[account(
+    init_if_needed,
+    payer = payer,
+    space = 8 + PresaleReceipt::INIT_SPACE,
+    seeds = [b"presale".as_ref(), payer.key().as_ref()],
+    bump
+  )]
+
+  pub presale_receipt: Option<Account<'info, PresaleReceipt>>,
+
+  // referred purchases only
+
+  /// CHECK: Never read or written, only compared with `referrer_earnings.referrer`
+  pub referrer: Option<UncheckedAccount<'info>>,
+
+  #[account(
+    mut,
+    seeds = [b"referral".as_ref(), referrer_earnings.referrer.as_ref()],
+    bump = referrer_earnings.bump,
+  )]
+
+  pub referrer_earnings: Option<Account<'info, ReferrerEarnings>>,
+
+  // no-loss lotteries only
+
+  #[account(
+    mut,
+    seeds = [b"vault".as_ref()],
+    bump = vault.bump,
+  )]
+
+  pub vault: Option<Account<'info, Vault>>,
+
+  // programmable tickets only
+
+  #[account(
+    mut,
+    seeds = [
+      b"metadata",
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref(),
+      b"token_record",
+      destination.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub token_record: Option<UncheckedAccount<'info>>,
+
+  #[account(address = sysvar::instructions::ID)]
+
+  /// CHECK: Instructions sysvar, address is checked
+  pub sysvar_instructions: Option<UncheckedAccount<'info>>,
+
+  #[account(address = token_auth_rules::ID)]
+
+  /// CHECK: Token auth rules program, address is checked
+  pub authorization_rules_program: Option<UncheckedAccount<'info>>,
+
+  #[account(
+    constraint = token_lottery.rule_set == Some(authorization_rules.key()) @ ErrorCode::IncorrectRuleSet
+  )]
+
+  /// CHECK: Rule set configured for this lottery, checked against `token_lottery.rule_set`
+  pub authorization_rules: Option<UncheckedAccount<'info>>,
+}
+
+impl<'info> BuyTicket<'info> {
+  pub fn pay_referral(&mut self, ticket_price: u64) -> Result<u64> {
+    credit_referrer(
+      &self.payer,
+      &self.token_lottery,
+      self.referrer.as_ref(),
+      self.referrer_earnings.as_mut(),
+      ticket_price,
+    )
+  }
+
+  pub fn mint_ticket(&self, ticket_data: DataV2, signer_seeds: &[&[&[u8]]]) -> Result<()> {
+    mint_to(
+      CpiContext::new_with_signer(
+        self.token_program.to_account_info(), 
+        MintTo {
+          mint: self.ticket_mint.to_account_info(),
+          to: self.destination.to_account_info(),
+          authority: self.collection_mint.to_account_info(),
+        }, 
+        signer_seeds,
+      ),
+      1,
+    )?;
+
+    msg!("Create Ticket Metadata account");
+
+    create_metadata_accounts_v3(
+      CpiContext::new_with_signer(
+        self.token_metadata_program.to_account_info(),
+        CreateMetadataAccountsV3 {
+          metadata: self.ticket_metadata.to_account_info(),
+          mint: self.ticket_mint.to_account_info(),
+          mint_authority: self.collection_mint.to_account_info(),
+          update_authority: self.collection_mint.to_account_info(),
+          payer: self.payer.to_account_info(),
+          system_program: self.system_program.to_account_info(),
+          rent: self.rent.to_account_info(),
+
+        },
+
+        signer_seeds,
+      ), 
+      ticket_data, 
+      true, 
+      true, 
+      None,
+    )?;
+
+
+    msg!("Creating ticket master edition account");
+    create_master_edition_v3(
+      CpiContext::new_with_signer(
+        self.token_metadata_program.to_account_info(),
+        CreateMasterEditionV3 {
+          payer: self.payer.to_account_info(),
+          mint: self.ticket_mint.to_account_info(),
+          edition: self.ticket_master_edition.to_account_info(),
+          mint_authority: self.collection_mint.to_account_info(),
+          update_authority: self.collection_mint.to_account_info(),
+          metadata: self.ticket_metadata.to_account_info(),
+          token_program: self.token_program.to_account_info(),
+          system_program: self.system_program.to_account_info(),
+          rent: self.rent.to_account_info()
+
+        },
+        signer_seeds
+        
+      ),
+      Some(0),
+    )?;
+
+    msg!("Verifying ticket metadata");
+    set_and_verify_sized_collection_item(
+      CpiContext::new_with_signer(
+        self.token_metadata_program.to_account_info(),
+        SetAndVerifySizedCollectionItem {
+          metadata: self.ticket_metadata.to_account_info(),
+          collection_authority: self.collection_mint.to_account_info(),
+          payer: self.payer.to_account_info(),
+          collection_mint: self.collection_mint.to_account_info(),
+          update_authority: self.collection_mint.to_account_info(),
+          collection_metadata: self.collection_metadata.to_account_info(),
+          collection_master_edition: self.collection_master_edition.to_account_info(),
+        },
+        signer_seeds
+      ), 
+      None
+    )?;
+
+    Ok(())
+  }
+
+  // programmable NFT ticket: royalties are enforced on transfer by the lottery's rule set
+  pub fn mint_programmable_ticket(&self, ticket_data: DataV2, signer_seeds: &[&[&[u8]]]) -> Result<()> {
+    let token_record = self.token_record.as_ref().ok_or(ErrorCode::MissingProgrammableAccounts)?;
+    let sysvar_instructions = self.sysvar_instructions.as_ref().ok_or(ErrorCode::MissingProgrammableAccounts)?;
+
+    let metadata_program = self.token_metadata_program.to_account_info();
+    let ticket_metadata = self.ticket_metadata.to_account_info();
+    let ticket_master_edition = self.ticket_master_edition.to_account_info();
+    let ticket_mint = self.ticket_mint.to_account_info();
+    let collection_mint = self.collection_mint.to_account_info();
+    let collection_metadata = self.collection_metadata.to_account_info();
+    let collection_master_edition = self.collection_master_edition.to_account_info();
+    let destination = self.destination.to_account_info();
+    let payer = self.payer.to_account_info();
+    let system_program = self.system_program.to_account_info();
+    let token_program = self.token_program.to_account_info();
+    let associated_token_program = self.associated_token_program.to_account_info();
+    let token_record = token_record.to_account_info();
+    let sysvar_instructions = sysvar_instructions.to_account_info();
+    let authorization_rules_program = self.authorization_rules_program.as_ref().map(|a| a.to_account_info());
+    let authorization_rules = self.authorization_rules.as_ref().map(|a| a.to_account_info());
+
+    msg!("Creating programmable ticket");
+
+    let mut create = CreateV1CpiBuilder::new(&metadata_program);
+    create
+      .metadata(&ticket_metadata)
+      .master_edition(Some(&ticket_master_edition))
+      .mint(&ticket_mint, false)
+      .authority(&collection_mint)
+      .payer(&payer)
+      .update_authority(&collection_mint, true)
+      .system_program(&system_program)
+      .sysvar_instructions(&sysvar_instructions)
+      .spl_token_program(Some(&token_program))
+      .name(ticket_data.name)
+      .symbol(ticket_data.symbol)
+      .uri(ticket_data.uri)
+      .seller_fee_basis_points(ticket_data.seller_fee_basis_points)
+      .primary_sale_happened(true)
+      .is_mutable(true)
+      .token_standard(TokenStandard::ProgrammableNonFungible)
+      .collection(Collection { verified: false, key: collection_mint.key() })
+      .print_supply(PrintSupply::Zero);
+
+    if let Some(creators) = ticket_data.creators {
+      create.creators(creators);
+    }
+
+    if let Some(rule_set) = self.token_lottery.rule_set {
+      create.rule_set(rule_set);
+    }
+
+    create.invoke_signed(signer_seeds)?;
+
+    msg!("Minting programmable ticket");
+    MintV1CpiBuilder::new(&metadata_program)
+      .token(&destination)
+      .token_owner(Some(&payer))
+      .metadata(&ticket_metadata)
+      .master_edition(Some(&ticket_master_edition))
+      .token_record(Some(&token_record))
+      .mint(&ticket_mint)
+      .authority(&collection_mint)
+      .payer(&payer)
+      .system_program(&system_program)
+      .sysvar_instructions(&sysvar_instructions)
+      .spl_token_program(&token_program)
+      .spl_ata_program(&associated_token_program)
+      .authorization_rules_program(authorization_rules_program.as_ref())
+      .authorization_rules(authorization_rules.as_ref())
+      .amount(1)
+      .invoke_signed(signer_seeds)?;
+
+    msg!("Verifying programmable ticket");
+    VerifyCollectionV1CpiBuilder::new(&metadata_program)
+      .authority(&collection_mint)
+      .metadata(&ticket_metadata)
+      .collection_mint(&collection_mint)
+      .collection_metadata(Some(&collection_metadata))
+      .collection_master_edition(Some(&collection_master_edition))
+      .system_program(&system_program)
+      .sysvar_instructions(&sysvar_instructions)
+      .invoke_signed(signer_seeds)?;
+
+    Ok(())
+  }
 }
 
 #[derive(Accounts)]
-pub struct CommitRandomness<'info> {
-  #[account(mut)]
-  pub payer: Signer<'info>,
+pub struct UpdateConfig<'info> {
+  pub authority: Signer<'info>,
 
   #[account(
     mut,
     seeds = [b"token_lottery".as_ref()],
     bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
   )]
 
   pub token_lottery: Account<'info, TokenLottery>,
 
-  /// CHECK: This account is checked by the Switchboard smart contract
-  pub randomness_account_data: UncheckedAccount<'info>, 
-
-  pub system_program: Program<'info, System>,
-
 }
 
 #[derive(Accounts)]
-pub struct ChooseWinner<'info> {
-  #[account(mut)]
-  pub payer: Signer<'info>,
+pub struct SetPaused<'info> {
+  pub authority: Signer<'info>,
 
   #[account(
     mut,
     seeds = [b"token_lottery".as_ref()],
-    bump = token_lottery.bump
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
   )]
 
   pub token_lottery: Account<'info, TokenLottery>,
 
-  /// CHECK: This account is checked by the Switchboard smart contract
-  pub randomness_account_data: UncheckedAccount<'info>, 
-
-  pub system_program: Program<'info, System>,
-
 }
 
 #[derive(Accounts)]
-pub struct ClaimPrize<'info> {
-  #[account(mut)]
-  pub payer: Signer<'info>,
+pub struct ProposeAuthority<'info> {
+  pub authority: Signer<'info>,
 
   #[account(
     mut,
     seeds = [b"token_lottery".as_ref()],
-    bump = token_lottery.bump
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
   )]
 
   pub token_lottery: Account<'info, TokenLottery>,
 
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+  pub new_authority: Signer<'info>,
+
   #[account(
-    seeds = [token_lottery.winner.to_le_bytes().as_ref()],
-    bump,
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.pending_authority == Some(new_authority.key()) @ ErrorCode::NotAuthorized,
   )]
 
-  pub ticket_mint: InterfaceAccount<'info, Mint>,
+  pub token_lottery: Account<'info, TokenLottery>,
+
+}
+
+#[derive(Accounts)]
+pub struct SetRole<'info> {
+  pub authority: Signer<'info>,
 
   #[account(
-    seeds = [b"collection_mint".as_ref()],
-    bump,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
   )]
 
-  pub collection_mint: InterfaceAccount<'info, Mint>,
+  pub token_lottery: Account<'info, TokenLottery>,
 
   #[account(
-    seeds = [
-      b"metadata", 
-      token_metadata_program.key().as_ref(),
-      ticket_mint.key().as_ref()
-      ],
-    bump,
-    seeds::program = token_metadata_program.key(),
+    mut,
+    seeds = [b"roles".as_ref()],
+    bump = roles.bump,
   )]
 
-  pub ticket_metadata: Account<'info, MetadataAccount>,
+  pub roles: Account<'info, Roles>,
+
+}
+
+#[derive(Accounts)]
+pub struct SetPresale<'info> {
+  pub authority: Signer<'info>,
 
   #[account(
-    associated_token::mint = ticket_mint,
-    associated_token::authority = payer,
-    associated_token::token_program = token_program,
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
   )]
 
-  pub destination: InterfaceAccount<'info, TokenAccount>,
+  pub token_lottery: Account<'info, TokenLottery>,
+
+}
+
+#[derive(Accounts)]
+pub struct SetPricingSchedule<'info> {
+  pub authority: Signer<'info>,
 
   #[account(
     mut,
-    seeds = [
-      b"metadata", token_metadata_program.key().as_ref(), 
-      collection_mint.key().as_ref()
-      ],
-    bump,
-    seeds::program = token_metadata_program.key(),
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
   )]
 
-  pub collection_metadata: Account<'info, MetadataAccount>,
+  pub token_lottery: Account<'info, TokenLottery>,
 
-  pub token_metadata_program: Program<'info, Metadata>,
-  pub token_program: Interface<'info, TokenInterface>,
+}
 
-  pub system_program: Program<'info, System>,
+#[derive(Accounts)]
+pub struct SetNoLoss<'info> {
+  #[account(mut)]
+  pub payer: Signer<'info>, // pays rent, does not have to be the authority
 
-}
+  pub authority: Signer<'info>,
 
-// define accounts for token_lottery smart contract
-#[account]
-#[derive(InitSpace)]
-pub struct TokenLottery {
-  pub bump: u8,
-  pub winner: u64,
-  pub winner_chosen: bool,
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    init_if_needed,
+    payer = payer,
+    space = 8 + Vault::INIT_SPACE,
+    seeds = [b"vault".as_ref()],
+    bump
+  )]
+
+  pub vault: Account<'info, Vault>,
+
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct SetReferralShare<'info> {
+  pub authority: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+  #[account(mut)]
+  pub referrer: Signer<'info>,
+
+  #[account(
+    init,
+    payer = referrer,
+    space = 8 + ReferrerEarnings::INIT_SPACE,
+    seeds = [b"referral".as_ref(), referrer.key().as_ref()],
+    bump
+  )]
+
+  pub referrer_earnings: Account<'info, ReferrerEarnings>,
+
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+  #[account(mut)]
+  pub referrer: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"referral".as_ref(), referrer.key().as_ref()],
+    bump = referrer_earnings.bump,
+    has_one = referrer,
+  )]
+
+  pub referrer_earnings: Account<'info, ReferrerEarnings>,
+
+}
+
+#[derive(Accounts)]
+pub struct InitializeLotteryToken2022<'info> {
+
+  #[account(mut)]
+  pub payer: Signer<'info>, // pays rent, does not have to be the authority
+
+  pub authority: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    init,
+    payer = payer,
+    mint::decimals = 0,
+    mint::authority = collection_mint,
+    mint::freeze_authority = collection_mint,
+    mint::token_program = token_program,
+    extensions::metadata_pointer::authority = collection_mint,
+    extensions::metadata_pointer::metadata_address = collection_mint,
+    extensions::group_pointer::authority = collection_mint,
+    extensions::group_pointer::group_address = collection_mint,
+    seeds = [b"collection_mint".as_ref()],
+    bump
+  )]
+
+  pub collection_mint: InterfaceAccount<'info, Mint>,
+
+  pub token_program: Program<'info, Token2022>,
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct BuyTicketToken2022<'info> {
+
+  #[account(mut)]
+  pub payer: Signer<'info>, //buyer
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    mut,
+    seeds = [b"collection_mint".as_ref()],
+    bump,
+    mint::token_program = token_program,
+  )]
+
+  pub collection_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    init,
+    payer = payer,
+    mint::decimals = 0,
+    mint::authority = collection_mint,
+    mint::freeze_authority = collection_mint,
+    mint::token_program = token_program,
+    extensions::metadata_pointer::authority = collection_mint,
+    extensions::metadata_pointer::metadata_address = ticket_mint,
+    extensions::group_member_pointer::authority = collection_mint,
+    extensions::group_member_pointer::member_address = ticket_mint,
+    seeds = [token_lottery.total_tickets.to_le_bytes().as_ref()],
+    bump
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    init,
+    payer = payer,
+    associated_token::mint = ticket_mint,
+    associated_token::authority = payer,
+    associated_token::token_program = token_program,
+  )]
+
+  pub destination: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(
+    init,
+    payer = payer,
+    space = 8 + TicketRecord::INIT_SPACE,
+    seeds = [b"ticket_record".as_ref(), ticket_mint.key().as_ref()],
+    bump
+  )]
+
+  pub ticket_record: Account<'info, TicketRecord>,
+
+  pub associated_token_program: Program<'info, AssociatedToken>,
+  pub token_program: Program<'info, Token2022>,
+  pub system_program: Program<'info, System>,
+
+  // presale purchases only
+
+  #[account(
+    init_if_needed,
+    payer = payer,
+    space = 8 + PresaleReceipt::INIT_SPACE,
+    seeds = [b"presale".as_ref(), payer.key().as_ref()],
+    bump
+  )]
+
+  pub presale_receipt: Option<Account<'info, PresaleReceipt>>,
+
+  // referred purchases only
+
+  /// CHECK: Never read or written, only compared with `referrer_earnings.referrer`
+  pub referrer: Option<UncheckedAccount<'info>>,
+
+  #[account(
+    mut,
+    seeds = [b"referral".as_ref(), referrer_earnings.referrer.as_ref()],
+    bump = referrer_earnings.bump,
+  )]
+
+  pub referrer_earnings: Option<Account<'info, ReferrerEarnings>>,
+
+  // no-loss lotteries only
+
+  #[account(
+    mut,
+    seeds = [b"vault".as_ref()],
+    bump = vault.bump,
+  )]
+
+  pub vault: Option<Account<'info, Vault>>,
+}
+
+impl<'info> BuyTicketToken2022<'info> {
+  pub fn pay_referral(&mut self, ticket_price: u64) -> Result<u64> {
+    credit_referrer(
+      &self.payer,
+      &self.token_lottery,
+      self.referrer.as_ref(),
+      self.referrer_earnings.as_mut(),
+      ticket_price,
+    )
+  }
+}
+
+#[derive(Accounts)]
+pub struct FundPot<'info> {
+  #[account(mut)]
+  pub sponsor: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    init_if_needed,
+    payer = sponsor,
+    space = 8 + Sponsorship::INIT_SPACE,
+    seeds = [b"sponsorship".as_ref(), sponsor.key().as_ref()],
+    bump
+  )]
+
+  pub sponsorship: Account<'info, Sponsorship>,
+
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct CancelLottery<'info> {
+  pub authority: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSponsorship<'info> {
+  #[account(mut)]
+  pub sponsor: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    mut,
+    close = sponsor,
+    seeds = [b"sponsorship".as_ref(), sponsor.key().as_ref()],
+    bump,
+    has_one = sponsor,
+  )]
+
+  pub sponsorship: Account<'info, Sponsorship>,
+
+}
+
+#[derive(Accounts)]
+pub struct RefundTicket<'info> {
+  #[account(mut)]
+  pub holder: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    address = ticket_record.ticket_mint,
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    associated_token::mint = ticket_mint,
+    associated_token::authority = holder,
+    associated_token::token_program = token_program,
+  )]
+
+  pub ticket_account: InterfaceAccount<'info, TokenAccount>,
+
+  // closed so each ticket is refunded once
+  #[account(
+    mut,
+    close = holder,
+    seeds = [b"ticket_record".as_ref(), ticket_record.ticket_mint.as_ref()],
+    bump,
+  )]
+
+  pub ticket_record: Account<'info, TicketRecord>,
+
+  pub token_program: Interface<'info, TokenInterface>,
+
+}
+
+#[derive(Accounts)]
+pub struct WithdrawPrincipal<'info> {
+  #[account(mut)]
+  pub holder: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    mut,
+    seeds = [b"vault".as_ref()],
+    bump = vault.bump,
+  )]
+
+  pub vault: Account<'info, Vault>,
+
+  #[account(
+    address = ticket_record.ticket_mint,
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    associated_token::mint = ticket_mint,
+    associated_token::authority = holder,
+    associated_token::token_program = token_program,
+  )]
+
+  pub ticket_account: InterfaceAccount<'info, TokenAccount>,
+
+  // closed so each ticket's deposit is paid out once
+  #[account(
+    mut,
+    close = holder,
+    seeds = [b"ticket_record".as_ref(), ticket_record.ticket_mint.as_ref()],
+    bump,
+  )]
+
+  pub ticket_record: Account<'info, TicketRecord>,
+
+  pub token_program: Interface<'info, TokenInterface>,
+
+}
+
+#[derive(Accounts)]
+pub struct HarvestYield<'info> {
+  pub payer: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    mut,
+    seeds = [b"vault".as_ref()],
+    bump = vault.bump,
+  )]
+
+  pub vault: Account<'info, Vault>,
+
+}
+
+#[derive(Accounts)]
+pub struct MigrateLottery<'info> {
+  #[account(mut)]
+  pub payer: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump,
+    owner = crate::ID,
+  )]
+
+  /// CHECK: Still in the v0 layout, decoded and checked by the instruction
+  pub token_lottery: UncheckedAccount<'info>,
+
+  #[account(
+    seeds = [b"collection_mint".as_ref()],
+    bump,
+  )]
+
+  /// CHECK: Only checked for existence, to carry over whether the collection was created
+  pub collection_mint: UncheckedAccount<'info>,
+
+  #[account(
+    init,
+    payer = payer,
+    space = 8 + Roles::INIT_SPACE,
+    seeds = [b"roles".as_ref()],
+    bump
+  )]
+
+  pub roles: Account<'info, Roles>,
+
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct ReclaimGuarantee<'info> {
+  #[account(mut)]
+  pub authority: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+    constraint = token_lottery.authority == authority.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+}
+
+#[derive(Accounts)]
+pub struct CommitRandomness<'info> {
+  // signs separately from the fee payer, so a multisig vault can act through CPI
+  pub operator: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump,
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [b"roles".as_ref()],
+    bump = roles.bump,
+  )]
+
+  pub roles: Account<'info, Roles>,
+
+  /// CHECK: This account is checked by the Switchboard smart contract
+  pub randomness_account_data: UncheckedAccount<'info>, 
+
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct ChooseWinner<'info> {
+  pub operator: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [b"roles".as_ref()],
+    bump = roles.bump,
+  )]
+
+  pub roles: Account<'info, Roles>,
+
+  /// CHECK: This account is checked by the Switchboard smart contract
+  pub randomness_account_data: UncheckedAccount<'info>, 
+
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct ClaimPrize<'info> {
+  #[account(mut)]
+  pub payer: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [token_lottery.winner.to_le_bytes().as_ref()],
+    bump,
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    seeds = [b"collection_mint".as_ref()],
+    bump,
+  )]
+
+  pub collection_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  pub ticket_metadata: Option<Account<'info, MetadataAccount>>, // none for Token-2022 tickets
+
+  #[account(
+    associated_token::mint = ticket_mint,
+    associated_token::authority = payer,
+    associated_token::token_program = token_program,
+  )]
+
+  pub destination: InterfaceAccount<'info, TokenAccount>,
+
+  pub token_metadata_program: Program<'info, Metadata>,
+  pub token_program: Interface<'info, TokenInterface>,
+
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+#[instruction(ticket_index: u64)]
+pub struct TransferTicket<'info> {
+  pub holder: Signer<'info>,
+
+  #[account(mut)]
+  pub recipient: Signer<'info>, // approves the lottery PDA on its ticket account
+
+  #[account(
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [ticket_index.to_le_bytes().as_ref()],
+    bump,
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    mut,
+    token::mint = ticket_mint,
+    token::authority = holder,
+    token::token_program = token_program,
+    constraint = ticket_account.delegate == Some(token_lottery.key()).into() @ ErrorCode::TicketNotDelegated,
+  )]
+
+  pub ticket_account: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(
+    init_if_needed,
+    payer = recipient,
+    associated_token::mint = ticket_mint,
+    associated_token::authority = recipient,
+    associated_token::token_program = token_program,
+  )]
+
+  pub recipient_ticket_account: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub ticket_metadata: UncheckedAccount<'info>,
+
+  #[account(
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref(),
+      b"edition"
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub ticket_master_edition: UncheckedAccount<'info>,
+
+  pub token_metadata_program: Program<'info, Metadata>,
+  pub token_program: Interface<'info, TokenInterface>,
+  pub associated_token_program: Program<'info, AssociatedToken>,
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+#[instruction(ticket_index: u64)]
+pub struct ThawTicket<'info> {
+  pub payer: Signer<'info>,
+
+  #[account(
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [ticket_index.to_le_bytes().as_ref()],
+    bump,
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    mut,
+    token::mint = ticket_mint,
+    token::token_program = token_program,
+    constraint = ticket_account.delegate == Some(token_lottery.key()).into() @ ErrorCode::TicketNotDelegated,
+  )]
+
+  pub ticket_account: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub ticket_metadata: UncheckedAccount<'info>,
+
+  #[account(
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref(),
+      b"edition"
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub ticket_master_edition: UncheckedAccount<'info>,
+
+  pub token_metadata_program: Program<'info, Metadata>,
+  pub token_program: Interface<'info, TokenInterface>,
+
+}
+
+#[derive(Accounts)]
+pub struct SweepRoyalties<'info> {
+  pub payer: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [b"roles".as_ref()],
+    bump = roles.bump,
+    constraint = roles.treasury == payer.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub roles: Account<'info, Roles>,
+
+}
+
+#[derive(Accounts)]
+pub struct WithdrawRoyalties<'info> {
+  #[account(mut)]
+  pub treasury: Signer<'info>,
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [b"roles".as_ref()],
+    bump = roles.bump,
+    constraint = roles.treasury == treasury.key() @ ErrorCode::NotAuthorized,
+  )]
+
+  pub roles: Account<'info, Roles>,
+
+}
+
+#[derive(Accounts)]
+pub struct UpdateTicketStatus<'info> {
+  #[account(mut)]
+  pub payer: Signer<'info>,
+
+  #[account(
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    mut,
+    seeds = [token_lottery.winner.to_le_bytes().as_ref()],
+    bump,
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    seeds = [b"collection_mint".as_ref()],
+    bump,
+  )]
+
+  pub collection_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    mut,
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  pub ticket_metadata: Option<Account<'info, MetadataAccount>>, // none for Token-2022 tickets
+
+  #[account(
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref(),
+      b"edition"
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  /// CHECK: This account is checked by the metadata smart contract
+  pub ticket_master_edition: Option<UncheckedAccount<'info>>, // programmable tickets only
+
+  #[account(address = sysvar::instructions::ID)]
+
+  /// CHECK: Instructions sysvar, address is checked
+  pub sysvar_instructions: Option<UncheckedAccount<'info>>, // programmable tickets only
+
+  pub token_metadata_program: Program<'info, Metadata>,
+  pub token_program: Interface<'info, TokenInterface>,
+  pub system_program: Program<'info, System>,
+
+}
+
+#[derive(Accounts)]
+pub struct SettlePrize<'info> {
+  #[account(mut)]
+  pub payer: Signer<'info>, // crank, does not need to own the ticket
+
+  #[account(
+    mut,
+    seeds = [b"token_lottery".as_ref()],
+    bump = token_lottery.bump
+  )]
+
+  pub token_lottery: Account<'info, TokenLottery>,
+
+  #[account(
+    seeds = [token_lottery.winner.to_le_bytes().as_ref()],
+    bump,
+  )]
+
+  pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    seeds = [b"collection_mint".as_ref()],
+    bump,
+  )]
+
+  pub collection_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+    seeds = [
+      b"metadata", 
+      token_metadata_program.key().as_ref(),
+      ticket_mint.key().as_ref()
+      ],
+    bump,
+    seeds::program = token_metadata_program.key(),
+  )]
+
+  pub ticket_metadata: Option<Account<'info, MetadataAccount>>, // none for Token-2022 tickets
+
+  #[account(
+    token::mint = ticket_mint,
+    token::token_program = token_program,
+  )]
+
+  pub ticket_account: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(
+    mut,
+    address = ticket_account.owner,
+  )]
+
+  /// CHECK: Only receives lamports; must be the owner of the winning ticket account
+  pub winner: UncheckedAccount<'info>,
+
+  pub token_metadata_program: Program<'info, Metadata>,
+  pub token_program: Interface<'info, TokenInterface>,
+
+  pub system_program: Program<'info, System>,
+
+}
+
+// define accounts for token_lottery smart contract
+#[account]
+#[derive(InitSpace, Default)]
+pub struct TokenLottery {
+  pub version: u8,
+  pub bump: u8,
+  pub winner: u64,
+  pub status: LotteryStatus,
+  pub start_time: u64,
+  pub end_time: u64,
+  pub lottery_pot_amount: u64,
+  pub total_tickets: u64,
+  pub ticket_price: u64,
+  pub authority: Pubkey,
+  pub randomness_account: Pubkey,
+  #[max_len(32)]
+  pub name: String,
+  #[max_len(10)]
+  pub symbol: String,
+  #[max_len(200)]
+  pub uri: String,
+  #[max_len(167)]
+  pub ticket_base_uri: String,
+  pub seller_fee_basis_points: u16,
+  pub pot_royalty_share: u8,
+  pub ticket_standard: TicketStandard,
+  pub rule_set: Option<Pubkey>,
+  pub freeze_after_close: bool,
+  pub presale_start_time: u64,
+  pub presale_merkle_root: [u8; 32], // all zero when there is no presale
+  pub presale_ticket_price: u64,
+  pub presale_max_per_wallet: u64,
+  pub pricing_schedule: PricingSchedule,
+  pub referral_share_bps: u16,
+  pub guaranteed_prize: u64, // escrowed by the authority, not part of the pot
+  pub pending_authority: Option<Pubkey>,
+  pub paused: bool,
+  pub no_loss: bool,
+  pub total_deposits: u64, // no-loss ticket payments held in the vault, owed back to ticket holders
+  pub swept_royalties: u64, // part of the pot that came from royalties rather than tickets or sponsors
+  // what is left of the 128 reserved bytes
+  pub reserved: [u64; 13],
+  pub reserved_tail: [u8; 7],
+}
+
+// layout of `TokenLottery` before it carried a version, kept to migrate old accounts
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Debug)]
+pub struct TokenLotteryV0 {
+  pub bump: u8,
+  pub winner: u64,
+  pub winner_chosen: bool,
   pub start_time: u64,
   pub end_time: u64,
   pub lottery_pot_amount: u64,
   pub total_tickets: u64,
   pub ticket_price: u64,
   pub authority: Pubkey,
-  pub randomness_account: Pubkey
+  pub randomness_account: Pubkey,
+}
+
+impl TokenLotteryV0 {
+  // metadata the v0 program hard-coded for every lottery
+  pub const NAME: &'static str = "Token Lottery Ticket #";
+  pub const SYMBOL: &'static str = "TLT";
+  pub const URI: &'static str = "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/metadata.json";
+
+  // v0 only had flags, the status is read back from them
+  pub fn status(&self, collection_created: bool) -> LotteryStatus {
+    if self.winner_chosen && self.lottery_pot_amount == 0 {
+      LotteryStatus::Claimed
+    } else if self.winner_chosen {
+      LotteryStatus::WinnerChosen
+    } else if self.randomness_account != Pubkey::default() {
+      LotteryStatus::RandomnessCommitted
+    } else if self.total_tickets > 0 {
+      LotteryStatus::Open
+    } else if collection_created {
+      LotteryStatus::CollectionReady
+    } else {
+      LotteryStatus::Created
+    }
+  }
+}
+
+// SOL a sponsor has added to the pot, refundable if the lottery is cancelled
+#[account]
+#[derive(InitSpace)]
+pub struct Sponsorship {
+  pub sponsor: Pubkey,
+  pub amount: u64,
+}
+
+// holds no-loss deposits apart from the pot, so the prize can never be paid out of principal
+#[account]
+#[derive(InitSpace)]
+pub struct Vault {
+  pub bump: u8,
+}
+
+// referral rewards held for one referrer until they claim them
+#[account]
+#[derive(InitSpace)]
+pub struct ReferrerEarnings {
+  pub bump: u8,
+  pub referrer: Pubkey,
+  pub unclaimed: u64,
+  pub total_earned: u64,
+}
+
+// what one ticket cost, kept so it can be refunded at the price actually paid
+#[account]
+#[derive(InitSpace)]
+pub struct TicketRecord {
+  pub ticket_mint: Pubkey,
+  pub buyer: Pubkey,
+  pub index: u64,
+  pub price_paid: u64,
+  pub referral_paid: u64, // already passed on to the referrer, not refundable from the pot
+}
+
+impl TicketRecord {
+  pub fn refundable_amount(&self) -> Result<u64> {
+    self
+      .price_paid
+      .checked_sub(self.referral_paid)
+      .ok_or(ErrorCode::MathOverflow.into())
+  }
+}
+
+// keys allowed to run the draw and to receive fees, the admin is `TokenLottery.authority`
+#[account]
+#[derive(InitSpace)]
+pub struct Roles {
+  pub bump: u8,
+  pub operator: Pubkey,
+  pub treasury: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+  Admin,
+  Operator,
+  Treasury,
+}
+
+#[event]
+pub struct RoleChanged {
+  pub role: Role,
+  pub previous: Pubkey,
+  pub new: Pubkey,
+}
+
+// presale tickets bought by one allowlisted wallet
+#[account]
+#[derive(InitSpace)]
+pub struct PresaleReceipt {
+  pub buyer: Pubkey,
+  pub tickets_bought: u64,
+}
+
+// sale window, price and guarantee a lottery is created with
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SaleConfig {
+  pub start_time: u64,
+  pub end_time: u64,
+  pub ticket_price: u64,
+  pub guaranteed_prize: u64,
+}
+
+// royalty and token standard options for the tickets of a lottery
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct TicketConfig {
+  pub seller_fee_basis_points: u16,
+  pub pot_royalty_share: u8, // percent of royalties paid to the pot, the rest goes to the treasury
+  pub ticket_standard: TicketStandard,
+  pub rule_set: Option<Pubkey>,
+  pub freeze_after_close: bool, // soulbound, only transfer_ticket moves tickets until sales close, legacy tickets only
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum LotteryStatus {
+  #[default]
+  Created,
+  CollectionReady,
+  Open,
+  Closed,
+  RandomnessCommitted,
+  WinnerChosen,
+  Claimed,
+  Cancelled,
+}
+
+// what an instruction is about to do, checked against the current status
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LotteryAction {
+  Configure,
+  InitializeCollection,
+  SellTicket,
+  FundPot,
+  CloseSales,
+  CommitRandomness,
+  ChooseWinner,
+  MarkWinner,
+  ClaimPrize,
+  TransferTicket,
+  ThawTicket,
+  Cancel,
+  Refund,
+  ReclaimGuarantee,
+  WithdrawPrincipal,
+  SweepRoyalties,
+  WithdrawRoyalties,
+}
+
+impl LotteryStatus {
+  // the transition table, `None` when the action is not allowed in this status
+  pub fn next(self, action: LotteryAction) -> Option<LotteryStatus> {
+    use LotteryAction as A;
+    use LotteryStatus as S;
+
+    match (self, action) {
+      (S::Created | S::CollectionReady, A::Configure) => Some(self),
+      (S::Created, A::InitializeCollection) => Some(S::CollectionReady),
+      (S::CollectionReady | S::Open, A::SellTicket) => Some(S::Open),
+      (S::Created | S::CollectionReady | S::Open | S::Closed, A::FundPot) => Some(self),
+      (S::Open, A::CloseSales) => Some(S::Closed),
+      // only once sales are over, and again until it is revealed
+      (S::Closed | S::RandomnessCommitted, A::CommitRandomness) => Some(S::RandomnessCommitted),
+      (S::RandomnessCommitted, A::ChooseWinner) => Some(S::WinnerChosen),
+      (S::WinnerChosen | S::Claimed, A::MarkWinner) => Some(self),
+      (S::WinnerChosen, A::ClaimPrize) => Some(S::Claimed),
+      (S::Open, A::TransferTicket) => Some(self),
+      // soulbound tickets are released once the lottery is settled either way
+      (S::Claimed | S::Cancelled, A::ThawTicket) => Some(self),
+      (S::Created | S::CollectionReady | S::Open | S::Closed, A::Cancel) => Some(S::Cancelled),
+      (S::Cancelled, A::Refund) => Some(self),
+      (S::Claimed | S::Cancelled, A::ReclaimGuarantee) => Some(self),
+      (S::WinnerChosen | S::Claimed | S::Cancelled, A::WithdrawPrincipal) => Some(self),
+      (S::Claimed | S::Cancelled, A::SweepRoyalties) => None,
+      (_, A::SweepRoyalties) => Some(self),
+      (S::Claimed | S::Cancelled, A::WithdrawRoyalties) => Some(self),
+      _ => None,
+    }
+  }
+
+  // most specific error for an action `next` refuses
+  pub fn rejection(self, action: LotteryAction) -> ErrorCode {
+    use LotteryAction as A;
+    use LotteryStatus as S;
+
+    match (self, action) {
+      (S::Cancelled, _) => ErrorCode::LotteryCancelled,
+      (_, A::Refund) => ErrorCode::LotteryNotCancelled,
+      (S::Created, A::SellTicket) => ErrorCode::CollectionNotInitialized,
+      (S::Claimed, A::ClaimPrize | A::SweepRoyalties) => ErrorCode::PrizeAlreadyClaimed,
+      (_, A::ThawTicket | A::ReclaimGuarantee | A::WithdrawRoyalties) => ErrorCode::PrizeNotClaimed,
+      (S::WinnerChosen | S::Claimed, _) => ErrorCode::WinnerChosen,
+      (_, A::MarkWinner | A::ClaimPrize | A::WithdrawPrincipal) => ErrorCode::WinnerNotChosen,
+      (S::RandomnessCommitted, _) => ErrorCode::DrawInProgress,
+      (_, A::Configure) => ErrorCode::ConfigLocked,
+      (_, A::SellTicket | A::CloseSales | A::TransferTicket) => ErrorCode::LotteryNotOpen,
+      (_, A::CommitRandomness) => ErrorCode::LotteryNotCompleted,
+      _ => ErrorCode::InvalidStatusTransition,
+    }
+  }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum TicketStandard {
+  #[default]
+  NonFungible,
+  ProgrammableNonFungible,
+  Token2022, // metadata and collection group stored in Token-2022 mint extensions
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PriceTier {
+  pub start_slot: u64,
+  pub price: u64,
+}
+
+// how the regular ticket price moves during the sale, `ticket_price` is the base
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, PartialEq, Eq, Default, Debug)]
+pub enum PricingSchedule {
+  #[default]
+  Fixed,
+  // each tier's price applies from its start slot, the base price before the first one
+  TimeTiers {
+    #[max_len(MAX_PRICE_TIERS)]
+    tiers: Vec<PriceTier>,
+  },
+  // base + increment for every ticket already sold
+  Linear { increment: u64 },
+  // base compounded by growth_bps for every ticket already sold
+  Exponential { growth_bps: u16 },
+}
+
+impl PricingSchedule {
+  pub fn validate(&self) -> Result<()> {
+    if let PricingSchedule::TimeTiers { tiers } = self {
+      require!(
+        !tiers.is_empty() && tiers.len() <= MAX_PRICE_TIERS,
+        ErrorCode::InvalidPricingSchedule
+      );
+      require!(
+        tiers.windows(2).all(|pair| pair[0].start_slot < pair[1].start_slot),
+        ErrorCode::InvalidPricingSchedule
+      );
+      require!(tiers.iter().all(|tier| tier.price > 0), ErrorCode::InvalidTicketPrice);
+    }
+
+    Ok(())
+  }
+
+  pub fn price(&self, base_price: u64, slot: u64, tickets_sold: u64) -> Result<u64> {
+    match self {
+      PricingSchedule::Fixed => Ok(base_price),
+      PricingSchedule::TimeTiers { tiers } => Ok(tiers
+        .iter()
+        .rev()
+        .find(|tier| tier.start_slot <= slot)
+        .map_or(base_price, |tier| tier.price)),
+      PricingSchedule::Linear { increment } => increment
+        .checked_mul(tickets_sold)
+        .and_then(|step| step.checked_add(base_price))
+        .ok_or(ErrorCode::MathOverflow.into()),
+      PricingSchedule::Exponential { growth_bps } => {
+        compound_bps(base_price, *growth_bps, tickets_sold)
+      }
+    }
+  }
+}
+
+// `amount * (1 + bps / 10_000) ^ periods` by square-and-multiply, rounded down
+pub fn compound_bps(amount: u64, bps: u16, periods: u64) -> Result<u64> {
+  const SCALE: u128 = 10_000;
+
+  let mut result = amount as u128;
+  let mut factor = SCALE + bps as u128;
+  let mut periods = periods;
+
+  while periods > 0 {
+    if periods & 1 == 1 {
+      result = result
+        .checked_mul(factor)
+        .ok_or(ErrorCode::MathOverflow)?
+        / SCALE;
+      require!(result <= u64::MAX as u128, ErrorCode::MathOverflow);
+    }
+    periods >>= 1;
+    if periods > 0 {
+      factor = factor.checked_mul(factor).ok_or(ErrorCode::MathOverflow)? / SCALE;
+    }
+  }
+
+  Ok(result as u64)
+}
+
+impl TokenLottery {
+  pub fn configure_tickets(
+    &mut self,
+    name: String,
+    symbol: String,
+    uri: String,
+    ticket_base_uri: String,
+    ticket_config: TicketConfig,
+  ) -> Result<()> {
+    let TicketConfig {
+      seller_fee_basis_points,
+      pot_royalty_share,
+      ticket_standard,
+      rule_set,
+      freeze_after_close,
+    } = ticket_config;
+    let ticket_base_uri = ticket_base_uri.trim_end_matches('/').to_string();
+
+    require!(name.len() <= MAX_NAME_LENGTH, ErrorCode::MetadataTooLong);
+    require!(symbol.len() <= MAX_SYMBOL_LENGTH, ErrorCode::MetadataTooLong);
+    require!(uri.len() <= MAX_URI_LENGTH, ErrorCode::MetadataTooLong);
+    require!(
+      ticket_base_uri.len() + MAX_TICKET_URI_SUFFIX_LENGTH <= MAX_URI_LENGTH,
+      ErrorCode::MetadataTooLong
+    );
+    require!(seller_fee_basis_points <= MAX_SELLER_FEE_BASIS_POINTS, ErrorCode::InvalidRoyalty);
+    require!(pot_royalty_share <= 100, ErrorCode::InvalidRoyalty);
+    // Token-2022 metadata has no royalty field for marketplaces to honour
+    require!(
+      seller_fee_basis_points == 0 || ticket_standard != TicketStandard::Token2022,
+      ErrorCode::InvalidRoyalty
+    );
+    require!(
+      rule_set.is_none() || ticket_standard == TicketStandard::ProgrammableNonFungible,
+      ErrorCode::InvalidTicketStandard
+    );
+    // freezing goes through the metadata program, and programmable tickets are
+    // already locked by their token record
+    require!(
+      !freeze_after_close || ticket_standard == TicketStandard::NonFungible,
+      ErrorCode::InvalidTicketStandard
+    );
+
+    self.name = name;
+    self.symbol = symbol;
+    self.uri = uri;
+    self.ticket_base_uri = ticket_base_uri;
+    self.seller_fee_basis_points = seller_fee_basis_points;
+    self.pot_royalty_share = pot_royalty_share;
+    self.ticket_standard = ticket_standard;
+    self.rule_set = rule_set;
+    self.freeze_after_close = freeze_after_close;
+
+    Ok(())
+  }
+
+  pub fn initialize(&mut self, bump: u8, authority: Pubkey, slot: u64, sale_config: &SaleConfig) -> Result<()> {
+    self.configure_sale(slot, sale_config.start_time, sale_config.end_time, sale_config.ticket_price)?;
+    self.version = TOKEN_LOTTERY_VERSION;
+    self.bump = bump;
+    self.authority = authority;
+    self.guaranteed_prize = sale_config.guaranteed_prize;
+
+    Ok(())
+  }
+
+  // decodes a raw v0 account, discriminator included, into the current layout
+  // v0 never stored where its ticket metadata lives, the authority passes it in
+  pub fn from_v0(data: &[u8], collection_created: bool, ticket_base_uri: String) -> Result<TokenLottery> {
+    require!(data.len() == 8 + TokenLotteryV0::INIT_SPACE, ErrorCode::AccountAlreadyMigrated);
+    require!(data[..8] == TokenLottery::DISCRIMINATOR, ErrorCode::AccountAlreadyMigrated);
+
+    let v0 = TokenLotteryV0::deserialize(&mut &data[8..])?;
+
+    let mut migrated = TokenLottery {
+      version: TOKEN_LOTTERY_VERSION,
+      bump: v0.bump,
+      winner: v0.winner,
+      status: v0.status(collection_created),
+      start_time: v0.start_time,
+      end_time: v0.end_time,
+      lottery_pot_amount: v0.lottery_pot_amount,
+      total_tickets: v0.total_tickets,
+      ticket_price: v0.ticket_price,
+      authority: v0.authority,
+      randomness_account: v0.randomness_account,
+      ..Default::default()
+    };
+
+    // v0 tickets were royalty-free legacy NFTs
+    migrated.configure_tickets(
+      TokenLotteryV0::NAME.to_string(),
+      TokenLotteryV0::SYMBOL.to_string(),
+      TokenLotteryV0::URI.to_string(),
+      ticket_base_uri,
+      TicketConfig {
+        seller_fee_basis_points: 0,
+        pot_royalty_share: 0,
+        ticket_standard: TicketStandard::NonFungible,
+        rule_set: None,
+        freeze_after_close: false,
+      },
+    )?;
+
+    Ok(migrated)
+  }
+
+  // lamports a v0 account holding `lamports` needs to stay rent-exempt at the current
+  // size without the rent eating into the pot
+  pub fn migration_rent_due(&self, rent: &Rent, lamports: u64) -> u64 {
+    rent
+      .minimum_balance(8 + TokenLottery::INIT_SPACE)
+      .saturating_add(self.lottery_pot_amount)
+      .saturating_sub(lamports)
+  }
+
+  pub fn configure_sale(
+    &mut self,
+    slot: u64,
+    start_time: u64,
+    end_time: u64,
+    ticket_price: u64,
+  ) -> Result<()> {
+    require!(start_time < end_time, ErrorCode::InvalidTimeWindow);
+    require!(end_time > slot, ErrorCode::InvalidTimeWindow);
+    require!(end_time - start_time <= MAX_SALE_WINDOW_SLOTS, ErrorCode::InvalidTimeWindow);
+    require!(ticket_price > 0, ErrorCode::InvalidTicketPrice);
+    // a configured presale has to end where regular sales begin
+    require!(
+      self.presale_merkle_root == [0; 32] || self.presale_start_time < start_time,
+      ErrorCode::InvalidTimeWindow
+    );
+
+    self.start_time = start_time;
+    self.end_time = end_time;
+    self.ticket_price = ticket_price;
+
+    Ok(())
+  }
+
+  pub fn configure_presale(
+    &mut self,
+    presale_start_time: u64,
+    merkle_root: [u8; 32],
+    presale_ticket_price: u64,
+    max_tickets_per_wallet: u64,
+  ) -> Result<()> {
+    require!(presale_start_time < self.start_time, ErrorCode::InvalidPresaleConfig);
+    require!(max_tickets_per_wallet > 0, ErrorCode::InvalidPresaleConfig);
+    require!(presale_ticket_price > 0, ErrorCode::InvalidTicketPrice);
+
+    self.presale_start_time = presale_start_time;
+    self.presale_merkle_root = merkle_root;
+    self.presale_ticket_price = presale_ticket_price;
+    self.presale_max_per_wallet = max_tickets_per_wallet;
+
+    Ok(())
+  }
+
+  // status `action` would move the lottery to, or the reason it is not allowed now
+  pub fn require_action(&self, action: LotteryAction) -> Result<LotteryStatus> {
+    self
+      .status
+      .next(action)
+      .ok_or_else(|| self.status.rejection(action).into())
+  }
+
+  pub fn advance(&mut self, action: LotteryAction) -> Result<()> {
+    self.status = self.require_action(action)?;
+
+    Ok(())
+  }
+
+  // sales close with the clock rather than an instruction, so the status catches up lazily
+  pub fn close_sales_if_ended(&mut self, slot: u64) -> Result<()> {
+    if self.status == LotteryStatus::Open && slot > self.end_time {
+      self.advance(LotteryAction::CloseSales)?;
+    }
+
+    Ok(())
+  }
+
+  // soulbound tickets stay frozen from purchase until settlement, an unfrozen one was
+  // moved outside the program and cannot win
+  pub fn require_ticket_frozen(&self, ticket_frozen: bool) -> Result<()> {
+    require!(!self.freeze_after_close || ticket_frozen, ErrorCode::TicketNotFrozen);
+
+    Ok(())
+  }
+
+  pub fn winner_drawn(&self) -> bool {
+    matches!(self.status, LotteryStatus::WinnerChosen | LotteryStatus::Claimed)
+  }
+
+  // settings can change until the first ticket is sold or sales open
+  pub fn require_config_unlocked(&self, slot: u64) -> Result<()> {
+    self.require_action(LotteryAction::Configure)?;
+    require!(self.total_tickets == 0, ErrorCode::ConfigLocked);
+    require!(slot < self.start_time, ErrorCode::ConfigLocked);
+
+    Ok(())
+  }
+
+  pub fn in_presale(&self, slot: u64) -> bool {
+    self.presale_merkle_root != [0; 32] && slot >= self.presale_start_time && slot < self.start_time
+  }
+
+  pub fn require_open(&self, slot: u64) -> Result<()> {
+    require!(slot >= self.start_time && slot <= self.end_time, ErrorCode::LotteryNotOpen);
+
+    Ok(())
+  }
+
+  pub fn require_accepting_funds(&self, slot: u64) -> Result<()> {
+    self.require_action(LotteryAction::FundPot)?;
+    require!(!self.paused, ErrorCode::LotteryPaused);
+    require!(slot <= self.end_time, ErrorCode::LotteryNotOpen);
+
+    Ok(())
+  }
+
+  // the winner gets the larger of the pot and the guarantee, the guarantee tops up the
+  // difference and keeps the rest for the authority to reclaim
+  pub fn pay_out_prize(&mut self) -> Result<u64> {
+    self.advance(LotteryAction::ClaimPrize)?;
+
+    let prize = self.lottery_pot_amount.max(self.guaranteed_prize);
+    let top_up = prize
+      .checked_sub(self.lottery_pot_amount)
+      .ok_or(ErrorCode::MathOverflow)?;
+
+    self.guaranteed_prize = self
+      .guaranteed_prize
+      .checked_sub(top_up)
+      .ok_or(ErrorCode::MathOverflow)?;
+    self.lottery_pot_amount = 0;
+    self.swept_royalties = 0;
+
+    Ok(prize)
+  }
+
+  // what the treasury can take out of `withdrawable` once the lottery is settled: everything
+  // but the pot still owed to ticket holders and sponsors, and the unused guarantee
+  pub fn take_leftover_royalties(&mut self, withdrawable: u64) -> Result<u64> {
+    self.require_action(LotteryAction::WithdrawRoyalties)?;
+
+    let pot_owed = self
+      .lottery_pot_amount
+      .checked_sub(self.swept_royalties)
+      .ok_or(ErrorCode::MathOverflow)?;
+    let owed = pot_owed
+      .checked_add(self.guaranteed_prize)
+      .ok_or(ErrorCode::MathOverflow)?;
+
+    self.lottery_pot_amount = pot_owed;
+    self.swept_royalties = 0;
+
+    Ok(withdrawable.saturating_sub(owed))
+  }
+
+  pub fn release_from_pot(&mut self, amount: u64) -> Result<()> {
+    self.lottery_pot_amount = self
+      .lottery_pot_amount
+      .checked_sub(amount)
+      .ok_or(ErrorCode::InsufficientFunds)?;
+
+    Ok(())
+  }
+
+  pub fn current_ticket_price(&self, slot: u64) -> Result<u64> {
+    self.pricing_schedule.price(self.ticket_price, slot, self.total_tickets)
+  }
+
+  // in no-loss mode the price is a deposit and stays out of the pot
+  pub fn record_ticket_sale(&mut self, price: u64) -> Result<()> {
+    let funds = if self.no_loss { self.total_deposits } else { self.lottery_pot_amount };
+    let funds = funds.checked_add(price).ok_or(ErrorCode::MathOverflow)?;
+    let total_tickets = self
+      .total_tickets
+      .checked_add(1)
+      .ok_or(ErrorCode::MathOverflow)?;
+
+    if self.no_loss {
+      self.total_deposits = funds;
+    } else {
+      self.lottery_pot_amount = funds;
+    }
+    self.total_tickets = total_tickets;
+
+    Ok(())
+  }
+
+  // counterpart of `record_ticket_sale` for money paid back on a ticket
+  pub fn release_ticket_funds(&mut self, amount: u64) -> Result<()> {
+    if !self.no_loss {
+      return self.release_from_pot(amount);
+    }
+
+    self.total_deposits = self
+      .total_deposits
+      .checked_sub(amount)
+      .ok_or(ErrorCode::InsufficientFunds)?;
+
+    Ok(())
+  }
+
+  pub fn winning_ticket_index(&self, random_value: u8) -> Result<u64> {
+    (random_value as u64)
+      .checked_rem(self.total_tickets)
+      .ok_or(ErrorCode::MathOverflow.into())
+  }
 }
 
 
@@ -727,7 +3992,1053 @@ pub enum ErrorCode {
   #[msg("Incorrect Ticket")]     
     IncorrectTicket,
   #[msg("No ticket")]
-    NoTicket
+    NoTicket,
+  #[msg("Insufficient funds above rent-exempt minimum")]
+    InsufficientFunds,
+  #[msg("Math overflow")]
+    MathOverflow,
+  #[msg("Metadata field exceeds the Metaplex length limit")]
+    MetadataTooLong,
+  #[msg("Royalty basis points or share out of range")]
+    InvalidRoyalty,
+  #[msg("Not supported for this lottery's ticket standard")]
+    InvalidTicketStandard,
+  #[msg("Programmable ticket accounts not provided")]
+    MissingProgrammableAccounts,
+  #[msg("Incorrect rule set")]
+    IncorrectRuleSet,
+  #[msg("Ticket freezing is not enabled for this lottery")]
+    FreezeNotEnabled,
+  #[msg("Ticket is not delegated to the lottery")]
+    TicketNotDelegated,
+  #[msg("Prize not claimed")]
+    PrizeNotClaimed,
+  #[msg("Invalid presale configuration")]
+    InvalidPresaleConfig,
+  #[msg("Wallet is not on the presale allowlist")]
+    NotAllowlisted,
+  #[msg("Presale ticket limit reached for this wallet")]
+    PresaleLimitReached,
+  #[msg("Invalid pricing schedule")]
+    InvalidPricingSchedule,
+  #[msg("Referral share out of range")]
+    InvalidReferralShare,
+  #[msg("Referrer and referrer earnings accounts must be provided together")]
+    MissingReferralAccounts,
+  #[msg("Buyer cannot refer themselves")]
+    SelfReferral,
+  #[msg("Sponsorship amount must be greater than zero")]
+    InvalidSponsorship,
+  #[msg("Lottery is cancelled")]
+    LotteryCancelled,
+  #[msg("Lottery is not cancelled")]
+    LotteryNotCancelled,
+  #[msg("Randomness already committed for the draw")]
+    DrawInProgress,
+  #[msg("Prize already claimed")]
+    PrizeAlreadyClaimed,
+  #[msg("Admin changes go through propose_authority and accept_authority")]
+    InvalidRole,
+  #[msg("Lottery is paused")]
+    LotteryPaused,
+  #[msg("Config can no longer change once sales have started")]
+    ConfigLocked,
+  #[msg("Sale window must end after it starts, in the future and within the maximum length")]
+    InvalidTimeWindow,
+  #[msg("Ticket price must be greater than zero")]
+    InvalidTicketPrice,
+  #[msg("Ticket collection has not been initialized")]
+    CollectionNotInitialized,
+  #[msg("Not allowed in the lottery's current status")]
+    InvalidStatusTransition,
+  #[msg("Account is not in the v0 layout")]
+    AccountAlreadyMigrated,
+  #[msg("Lottery is not in no-loss mode")]
+    NoLossNotEnabled,
+  #[msg("Vault account required in no-loss mode")]
+    MissingVault,
+  #[msg("Referrer earnings account belongs to another referrer")]
+    IncorrectReferrer,
+  #[msg("Ticket has to be frozen before it can win")]
+    TicketNotFrozen,
+  #[msg("No-loss deposits are returned to ticket holders through withdraw_principal")]
+    NoLossRefund
 
     
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a drawn but unclaimed lottery as the unversioned program stored it
+  fn v0_snapshot(authority: Pubkey, randomness_account: Pubkey) -> Vec<u8> {
+    let mut data = TokenLottery::DISCRIMINATOR.to_vec();
+    data.push(254); // bump
+    data.extend_from_slice(&3u64.to_le_bytes()); // winner
+    data.push(1); // winner_chosen
+    data.extend_from_slice(&10u64.to_le_bytes()); // start_time
+    data.extend_from_slice(&50u64.to_le_bytes()); // end_time
+    data.extend_from_slice(&70_000u64.to_le_bytes()); // lottery_pot_amount
+    data.extend_from_slice(&7u64.to_le_bytes()); // total_tickets
+    data.extend_from_slice(&10_000u64.to_le_bytes()); // ticket_price
+    data.extend_from_slice(authority.as_ref());
+    data.extend_from_slice(randomness_account.as_ref());
+    data
+  }
+
+  const V0_TICKET_BASE_URI: &str = "https://example.com/tickets";
+
+  #[test]
+  fn v0_snapshot_migrates_to_current_layout() {
+    let authority = Pubkey::new_unique();
+    let randomness_account = Pubkey::new_unique();
+    let snapshot = v0_snapshot(authority, randomness_account);
+
+    assert_eq!(snapshot.len(), 8 + TokenLotteryV0::INIT_SPACE);
+
+    let migrated = TokenLottery::from_v0(&snapshot, true, format!("{}/", V0_TICKET_BASE_URI)).unwrap();
+
+    assert_eq!(migrated.version, TOKEN_LOTTERY_VERSION);
+    assert_eq!(migrated.bump, 254);
+    assert_eq!(migrated.winner, 3);
+    assert_eq!(migrated.status, LotteryStatus::WinnerChosen);
+    assert_eq!((migrated.start_time, migrated.end_time), (10, 50));
+    assert_eq!(migrated.lottery_pot_amount, 70_000);
+    assert_eq!(migrated.total_tickets, 7);
+    assert_eq!(migrated.ticket_price, 10_000);
+    assert_eq!(migrated.authority, authority);
+    assert_eq!(migrated.randomness_account, randomness_account);
+    assert_eq!(ticket_name(&migrated.name, 3), "Token Lottery Ticket #3");
+    assert_eq!(ticket_uri(&migrated.ticket_base_uri, 3), "https://example.com/tickets/3.json");
+    assert_eq!(migrated.ticket_standard, TicketStandard::NonFungible);
+
+    // the migrated account round-trips through the regular account decoder
+    let mut data = vec![0; 8 + TokenLottery::INIT_SPACE];
+    migrated.try_serialize(&mut &mut data[..]).unwrap();
+    let decoded = TokenLottery::try_deserialize(&mut &data[..]).unwrap();
+
+    assert_eq!(decoded.authority, authority);
+    assert_eq!(decoded.status, LotteryStatus::WinnerChosen);
+    assert_eq!((decoded.no_loss, decoded.total_deposits), (false, 0));
+  }
+
+  #[test]
+  fn v0_flags_map_to_status() {
+    let v0 = TokenLotteryV0::deserialize(&mut &v0_snapshot(Pubkey::new_unique(), Pubkey::new_unique())[8..]).unwrap();
+
+    assert_eq!(TokenLotteryV0 { lottery_pot_amount: 0, ..v0.clone() }.status(true), LotteryStatus::Claimed);
+
+    let undrawn = TokenLotteryV0 { winner_chosen: false, ..v0 };
+    assert_eq!(undrawn.status(true), LotteryStatus::RandomnessCommitted);
+
+    let uncommitted = TokenLotteryV0 { randomness_account: Pubkey::default(), ..undrawn };
+    assert_eq!(uncommitted.status(true), LotteryStatus::Open);
+
+    let unsold = TokenLotteryV0 { total_tickets: 0, ..uncommitted };
+    assert_eq!(unsold.status(true), LotteryStatus::CollectionReady);
+    assert_eq!(unsold.status(false), LotteryStatus::Created);
+  }
+
+  #[test]
+  fn migration_rejects_current_accounts() {
+    let lottery = TokenLottery { version: TOKEN_LOTTERY_VERSION, ..Default::default() };
+    let mut data = vec![0; 8 + TokenLottery::INIT_SPACE];
+    lottery.try_serialize(&mut &mut data[..]).unwrap();
+
+    assert_eq!(
+      TokenLottery::from_v0(&data, true, V0_TICKET_BASE_URI.to_string()).err(),
+      Some(ErrorCode::AccountAlreadyMigrated.into())
+    );
+
+    let mut snapshot = v0_snapshot(Pubkey::new_unique(), Pubkey::new_unique());
+    snapshot[0] ^= 1;
+    assert_eq!(
+      TokenLottery::from_v0(&snapshot, true, V0_TICKET_BASE_URI.to_string()).err(),
+      Some(ErrorCode::AccountAlreadyMigrated.into())
+    );
+  }
+
+  #[test]
+  fn migration_validates_ticket_base_uri() {
+    let snapshot = v0_snapshot(Pubkey::new_unique(), Pubkey::new_unique());
+    let too_long = format!("https://example.com/{}", "a".repeat(MAX_URI_LENGTH));
+
+    assert_eq!(TokenLottery::from_v0(&snapshot, true, too_long).err(), Some(ErrorCode::MetadataTooLong.into()));
+  }
+
+  #[test]
+  fn migration_tops_up_rent_for_the_larger_account() {
+    let rent = Rent::default();
+    let migrated = TokenLottery::from_v0(
+      &v0_snapshot(Pubkey::new_unique(), Pubkey::new_unique()),
+      true,
+      V0_TICKET_BASE_URI.to_string(),
+    )
+    .unwrap();
+    let v0_minimum = rent.minimum_balance(8 + TokenLotteryV0::INIT_SPACE);
+    let new_minimum = rent.minimum_balance(8 + TokenLottery::INIT_SPACE);
+
+    // the 70_000 lamport pot stays withdrawable after the resize
+    assert_eq!(migrated.migration_rent_due(&rent, v0_minimum + 70_000), new_minimum - v0_minimum);
+    assert_eq!(migrated.migration_rent_due(&rent, new_minimum), 70_000);
+    assert_eq!(migrated.migration_rent_due(&rent, new_minimum + 70_000), 0);
+  }
+
+  #[test]
+  fn configure_sale_validates_window_and_price() {
+    let mut lottery = TokenLottery::default();
+
+    lottery.configure_sale(0, 100, 200, 10_000).unwrap();
+    assert_eq!((lottery.start_time, lottery.end_time, lottery.ticket_price), (100, 200, 10_000));
+
+    assert_eq!(lottery.configure_sale(0, 200, 200, 10_000).unwrap_err(), ErrorCode::InvalidTimeWindow.into());
+    assert_eq!(lottery.configure_sale(0, 100, 200, 0).unwrap_err(), ErrorCode::InvalidTicketPrice.into());
+
+    lottery.presale_merkle_root = [1; 32];
+    lottery.presale_start_time = 50;
+    assert_eq!(lottery.configure_sale(0, 50, 200, 10_000).unwrap_err(), ErrorCode::InvalidTimeWindow.into());
+    assert_eq!((lottery.start_time, lottery.end_time, lottery.ticket_price), (100, 200, 10_000));
+  }
+
+  #[test]
+  fn configure_sale_rejects_past_and_overlong_windows() {
+    let mut lottery = TokenLottery::default();
+
+    assert_eq!(lottery.configure_sale(200, 100, 200, 10_000).unwrap_err(), ErrorCode::InvalidTimeWindow.into());
+    // a window that has started but not ended is still accepted
+    assert!(lottery.configure_sale(150, 100, 200, 10_000).is_ok());
+
+    assert!(lottery.configure_sale(0, 100, 100 + MAX_SALE_WINDOW_SLOTS, 10_000).is_ok());
+    assert_eq!(
+      lottery.configure_sale(0, 100, 101 + MAX_SALE_WINDOW_SLOTS, 10_000).unwrap_err(),
+      ErrorCode::InvalidTimeWindow.into()
+    );
+  }
+
+  #[test]
+  fn config_locks_once_sales_start() {
+    let mut lottery = TokenLottery { start_time: 100, end_time: 200, ..Default::default() };
+
+    assert!(lottery.require_config_unlocked(99).is_ok());
+    assert_eq!(lottery.require_config_unlocked(100).unwrap_err(), ErrorCode::ConfigLocked.into());
+
+    lottery.total_tickets = 1;
+    assert_eq!(lottery.require_config_unlocked(0).unwrap_err(), ErrorCode::ConfigLocked.into());
+  }
+
+  #[test]
+  fn record_ticket_sale_updates_pot_and_counter() {
+    let mut lottery = TokenLottery { ticket_price: 10_000, ..Default::default() };
+
+    lottery.record_ticket_sale(10_000).unwrap();
+    lottery.record_ticket_sale(10_000).unwrap();
+
+    assert_eq!(lottery.lottery_pot_amount, 20_000);
+    assert_eq!(lottery.total_tickets, 2);
+  }
+
+  #[test]
+  fn no_loss_sales_are_deposits_not_pot() {
+    let mut lottery = TokenLottery { no_loss: true, lottery_pot_amount: 5_000, ..Default::default() };
+
+    lottery.record_ticket_sale(10_000).unwrap();
+    lottery.record_ticket_sale(12_000).unwrap();
+
+    assert_eq!(lottery.total_deposits, 22_000);
+    assert_eq!(lottery.lottery_pot_amount, 5_000);
+    assert_eq!(lottery.total_tickets, 2);
+
+    lottery.release_ticket_funds(10_000).unwrap();
+    assert_eq!(lottery.total_deposits, 12_000);
+    assert_eq!(lottery.lottery_pot_amount, 5_000);
+
+    assert_eq!(lottery.release_ticket_funds(12_001).unwrap_err(), ErrorCode::InsufficientFunds.into());
+  }
+
+  #[test]
+  fn principal_is_withdrawable_after_the_draw_or_a_cancellation() {
+    for status in ALL_STATUSES {
+      let withdrawable = matches!(
+        status,
+        LotteryStatus::WinnerChosen | LotteryStatus::Claimed | LotteryStatus::Cancelled
+      );
+
+      assert_eq!(status.next(LotteryAction::WithdrawPrincipal).is_some(), withdrawable, "{:?}", status);
+    }
+
+    assert_eq!(
+      Error::from(LotteryStatus::RandomnessCommitted.rejection(LotteryAction::WithdrawPrincipal)),
+      ErrorCode::WinnerNotChosen.into()
+    );
+  }
+
+  #[test]
+  fn record_ticket_sale_accepts_last_representable_values() {
+    let mut lottery = TokenLottery {
+      lottery_pot_amount: u64::MAX - 1,
+      total_tickets: u64::MAX - 1,
+      ..Default::default()
+    };
+
+    lottery.record_ticket_sale(1).unwrap();
+
+    assert_eq!(lottery.lottery_pot_amount, u64::MAX);
+    assert_eq!(lottery.total_tickets, u64::MAX);
+  }
+
+  #[test]
+  fn record_ticket_sale_rejects_pot_overflow() {
+    let mut lottery = TokenLottery { lottery_pot_amount: u64::MAX, ..Default::default() };
+
+    let err = lottery.record_ticket_sale(1).unwrap_err();
+
+    assert_eq!(err, ErrorCode::MathOverflow.into());
+    assert_eq!(lottery.lottery_pot_amount, u64::MAX);
+    assert_eq!(lottery.total_tickets, 0);
+  }
+
+  #[test]
+  fn record_ticket_sale_rejects_ticket_counter_overflow() {
+    let mut lottery = TokenLottery { total_tickets: u64::MAX, ..Default::default() };
+
+    let err = lottery.record_ticket_sale(0).unwrap_err();
+
+    assert_eq!(err, ErrorCode::MathOverflow.into());
+    assert_eq!(lottery.lottery_pot_amount, 0);
+    assert_eq!(lottery.total_tickets, u64::MAX);
+  }
+
+  fn drawn_lottery() -> TokenLottery {
+    TokenLottery { winner: 3, status: LotteryStatus::WinnerChosen, total_tickets: 7, ..Default::default() }
+  }
+
+  // a Token-2022 ticket mint, in `group` when one is given
+  fn token_2022_ticket_mint(group: Option<Pubkey>) -> Vec<u8> {
+    use anchor_spl::token_2022::spl_token_2022::{
+      extension::{BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut},
+      state::Mint,
+    };
+
+    let extensions: &[ExtensionType] = if group.is_some() { &[ExtensionType::TokenGroupMember] } else { &[] };
+    let mut data = vec![0; ExtensionType::try_calculate_account_len::<Mint>(extensions).unwrap()];
+    let mut mint = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+
+    if let Some(group) = group {
+      let member = mint.init_extension::<TokenGroupMember>(true).unwrap();
+      member.group = group;
+      member.member_number = 1.into();
+    }
+
+    mint.base = Mint { is_initialized: true, supply: 1, ..Default::default() };
+    mint.pack_base();
+    if group.is_some() {
+      mint.init_account_type().unwrap();
+    }
+    data
+  }
+
+  fn token_2022_collection(mint_data: &mut [u8]) -> Result<Option<Collection>> {
+    let key = Pubkey::new_unique();
+    let owner = spl_token_2022::ID;
+    let mut lamports = 0;
+    let ticket_mint = AccountInfo::new(&key, false, false, &mut lamports, mint_data, &owner, false, 0);
+    let lottery = TokenLottery { ticket_standard: TicketStandard::Token2022, ..drawn_lottery() };
+
+    ticket_collection(&lottery, None, &ticket_mint)
+  }
+
+  #[test]
+  fn token_2022_ticket_is_verified_by_its_group() {
+    let collection_mint = Pubkey::new_unique();
+    let mut mint_data = token_2022_ticket_mint(Some(collection_mint));
+    let lottery = TokenLottery { ticket_standard: TicketStandard::Token2022, ..drawn_lottery() };
+
+    let collection = token_2022_collection(&mut mint_data).unwrap();
+    assert!(validate_winning_ticket(&lottery, collection.as_ref(), &collection_mint, 1).is_ok());
+    assert_eq!(
+      validate_winning_ticket(&lottery, collection.as_ref(), &Pubkey::new_unique(), 1).unwrap_err(),
+      ErrorCode::IncorrectTicket.into()
+    );
+  }
+
+  #[test]
+  fn token_2022_ticket_outside_a_group_is_not_verified() {
+    let mut mint_data = token_2022_ticket_mint(None);
+    let lottery = TokenLottery { ticket_standard: TicketStandard::Token2022, ..drawn_lottery() };
+
+    let collection = token_2022_collection(&mut mint_data).unwrap();
+    assert!(collection.is_none());
+    assert_eq!(
+      validate_winning_ticket(&lottery, collection.as_ref(), &Pubkey::new_unique(), 1).unwrap_err(),
+      ErrorCode::NotVerified.into()
+    );
+  }
+
+  #[test]
+  fn soulbound_lottery_only_pays_frozen_tickets() {
+    let lottery = TokenLottery { freeze_after_close: true, ..drawn_lottery() };
+
+    assert!(lottery.require_ticket_frozen(true).is_ok());
+    assert_eq!(lottery.require_ticket_frozen(false).unwrap_err(), ErrorCode::TicketNotFrozen.into());
+
+    // tickets stay freely transferable without the mode
+    assert!(drawn_lottery().require_ticket_frozen(false).is_ok());
+  }
+
+  #[test]
+  fn validate_winning_ticket_accepts_verified_ticket() {
+    let collection_mint = Pubkey::new_unique();
+    let collection = Collection { verified: true, key: collection_mint };
+
+    validate_winning_ticket(&drawn_lottery(), Some(&collection), &collection_mint, 1).unwrap();
+  }
+
+  #[test]
+  fn validate_winning_ticket_rejects_undrawn_lottery() {
+    let collection_mint = Pubkey::new_unique();
+    let collection = Collection { verified: true, key: collection_mint };
+    let lottery = TokenLottery { status: LotteryStatus::RandomnessCommitted, ..drawn_lottery() };
+
+    let err = validate_winning_ticket(&lottery, Some(&collection), &collection_mint, 1).unwrap_err();
+
+    assert_eq!(err, ErrorCode::WinnerNotChosen.into());
+  }
+
+  #[test]
+  fn validate_winning_ticket_rejects_missing_collection() {
+    let err = validate_winning_ticket(&drawn_lottery(), None, &Pubkey::new_unique(), 1).unwrap_err();
+
+    assert_eq!(err, ErrorCode::NotVerified.into());
+  }
+
+  #[test]
+  fn validate_winning_ticket_rejects_unverified_collection() {
+    let collection_mint = Pubkey::new_unique();
+    let collection = Collection { verified: false, key: collection_mint };
+
+    let err = validate_winning_ticket(&drawn_lottery(), Some(&collection), &collection_mint, 1).unwrap_err();
+
+    assert_eq!(err, ErrorCode::NotVerified.into());
+  }
+
+  #[test]
+  fn validate_winning_ticket_rejects_wrong_collection() {
+    let collection = Collection { verified: true, key: Pubkey::new_unique() };
+
+    let err = validate_winning_ticket(&drawn_lottery(), Some(&collection), &Pubkey::new_unique(), 1).unwrap_err();
+
+    assert_eq!(err, ErrorCode::IncorrectTicket.into());
+  }
+
+  #[test]
+  fn validate_winning_ticket_rejects_zero_balance() {
+    let collection_mint = Pubkey::new_unique();
+    let collection = Collection { verified: true, key: collection_mint };
+
+    let err = validate_winning_ticket(&drawn_lottery(), Some(&collection), &collection_mint, 0).unwrap_err();
+
+    assert_eq!(err, ErrorCode::NoTicket.into());
+  }
+
+  fn hash_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    if a <= b {
+      keccak::hashv(&[&a, &b]).to_bytes()
+    } else {
+      keccak::hashv(&[&b, &a]).to_bytes()
+    }
+  }
+
+  // four-wallet allowlist, returns the root and each wallet's proof
+  fn allowlist(wallets: &[Pubkey; 4]) -> ([u8; 32], Vec<Vec<[u8; 32]>>) {
+    let leaves: Vec<[u8; 32]> = wallets.iter().map(|w| keccak::hash(w.as_ref()).to_bytes()).collect();
+    let left = hash_pair(leaves[0], leaves[1]);
+    let right = hash_pair(leaves[2], leaves[3]);
+    let proofs = vec![
+      vec![leaves[1], right],
+      vec![leaves[0], right],
+      vec![leaves[3], left],
+      vec![leaves[2], left],
+    ];
+
+    (hash_pair(left, right), proofs)
+  }
+
+  fn presale_lottery(root: [u8; 32]) -> TokenLottery {
+    TokenLottery {
+      start_time: 100,
+      end_time: 200,
+      ticket_price: 10_000,
+      presale_start_time: 50,
+      presale_merkle_root: root,
+      presale_ticket_price: 5_000,
+      presale_max_per_wallet: 2,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn guarantee_tops_up_a_small_pot() {
+    let mut lottery = TokenLottery {
+      lottery_pot_amount: 30_000,
+      guaranteed_prize: 100_000,
+      status: LotteryStatus::WinnerChosen,
+      ..Default::default()
+    };
+
+    assert_eq!(lottery.pay_out_prize().unwrap(), 100_000);
+    assert_eq!(lottery.lottery_pot_amount, 0);
+    assert_eq!(lottery.guaranteed_prize, 30_000);
+    assert_eq!(lottery.status, LotteryStatus::Claimed);
+  }
+
+  #[test]
+  fn pot_above_guarantee_leaves_guarantee_unused() {
+    let mut lottery = TokenLottery {
+      lottery_pot_amount: 150_000,
+      guaranteed_prize: 100_000,
+      status: LotteryStatus::WinnerChosen,
+      ..Default::default()
+    };
+
+    assert_eq!(lottery.pay_out_prize().unwrap(), 150_000);
+    assert_eq!(lottery.guaranteed_prize, 100_000);
+  }
+
+  #[test]
+  fn prize_is_paid_out_once() {
+    let mut lottery = TokenLottery {
+      lottery_pot_amount: 10_000,
+      guaranteed_prize: 50_000,
+      status: LotteryStatus::WinnerChosen,
+      ..Default::default()
+    };
+
+    lottery.pay_out_prize().unwrap();
+
+    assert_eq!(lottery.pay_out_prize().unwrap_err(), ErrorCode::PrizeAlreadyClaimed.into());
+    assert_eq!(lottery.guaranteed_prize, 10_000);
+  }
+
+  #[test]
+  fn release_from_pot_cannot_overdraw() {
+    let mut lottery = TokenLottery { lottery_pot_amount: 1_000, ..Default::default() };
+
+    lottery.release_from_pot(400).unwrap();
+    assert_eq!(lottery.lottery_pot_amount, 600);
+
+    assert_eq!(lottery.release_from_pot(601).unwrap_err(), ErrorCode::InsufficientFunds.into());
+    assert_eq!(lottery.lottery_pot_amount, 600);
+  }
+
+  #[test]
+  fn cancelled_lottery_stops_sales_and_funding() {
+    let mut lottery = TokenLottery { start_time: 100, end_time: 200, status: LotteryStatus::Cancelled, ..Default::default() };
+
+    assert_eq!(lottery.advance(LotteryAction::SellTicket).unwrap_err(), ErrorCode::LotteryCancelled.into());
+    assert_eq!(lottery.require_accepting_funds(150).unwrap_err(), ErrorCode::LotteryCancelled.into());
+  }
+
+  const ALL_STATUSES: [LotteryStatus; 8] = [
+    LotteryStatus::Created,
+    LotteryStatus::CollectionReady,
+    LotteryStatus::Open,
+    LotteryStatus::Closed,
+    LotteryStatus::RandomnessCommitted,
+    LotteryStatus::WinnerChosen,
+    LotteryStatus::Claimed,
+    LotteryStatus::Cancelled,
+  ];
+
+  fn run(status: LotteryStatus, actions: &[LotteryAction]) -> Option<LotteryStatus> {
+    actions.iter().try_fold(status, |status, action| status.next(*action))
+  }
+
+  #[test]
+  fn status_follows_the_happy_path() {
+    let status = run(
+      LotteryStatus::Created,
+      &[
+        LotteryAction::Configure,
+        LotteryAction::InitializeCollection,
+        LotteryAction::Configure,
+        LotteryAction::SellTicket,
+        LotteryAction::SellTicket,
+        LotteryAction::FundPot,
+        LotteryAction::CloseSales,
+        LotteryAction::CommitRandomness,
+        LotteryAction::CommitRandomness,
+        LotteryAction::ChooseWinner,
+        LotteryAction::MarkWinner,
+        LotteryAction::ClaimPrize,
+        LotteryAction::ThawTicket,
+        LotteryAction::ReclaimGuarantee,
+      ],
+    );
+
+    assert_eq!(status, Some(LotteryStatus::Claimed));
+  }
+
+  #[test]
+  fn draw_waits_for_sales_to_close() {
+    assert_eq!(LotteryStatus::Open.next(LotteryAction::CommitRandomness), None);
+    assert_eq!(
+      Error::from(LotteryStatus::Open.rejection(LotteryAction::CommitRandomness)),
+      ErrorCode::LotteryNotCompleted.into()
+    );
+
+    let status = run(
+      LotteryStatus::Open,
+      &[LotteryAction::CloseSales, LotteryAction::CommitRandomness, LotteryAction::ChooseWinner],
+    );
+
+    assert_eq!(status, Some(LotteryStatus::WinnerChosen));
+    assert_eq!(LotteryStatus::RandomnessCommitted.next(LotteryAction::SellTicket), None);
+  }
+
+  #[test]
+  fn cancel_is_only_possible_before_the_draw() {
+    for status in ALL_STATUSES {
+      let cancellable = matches!(
+        status,
+        LotteryStatus::Created | LotteryStatus::CollectionReady | LotteryStatus::Open | LotteryStatus::Closed
+      );
+
+      assert_eq!(status.next(LotteryAction::Cancel).is_some(), cancellable, "{:?}", status);
+    }
+
+    assert_eq!(
+      run(LotteryStatus::Open, &[LotteryAction::Cancel, LotteryAction::Refund, LotteryAction::ReclaimGuarantee]),
+      Some(LotteryStatus::Cancelled)
+    );
+  }
+
+  #[test]
+  fn cancelled_and_claimed_lotteries_only_pay_out() {
+    let actions = [
+      LotteryAction::Configure,
+      LotteryAction::InitializeCollection,
+      LotteryAction::SellTicket,
+      LotteryAction::FundPot,
+      LotteryAction::CloseSales,
+      LotteryAction::CommitRandomness,
+      LotteryAction::ChooseWinner,
+      LotteryAction::ClaimPrize,
+      LotteryAction::Cancel,
+    ];
+
+    for action in actions {
+      assert_eq!(LotteryStatus::Cancelled.next(action), None, "{:?}", action);
+      assert_eq!(LotteryStatus::Claimed.next(action), None, "{:?}", action);
+    }
+  }
+
+  #[test]
+  fn frozen_tickets_thaw_once_claimed_or_cancelled() {
+    for status in ALL_STATUSES {
+      let thawable = matches!(status, LotteryStatus::Claimed | LotteryStatus::Cancelled);
+
+      assert_eq!(status.next(LotteryAction::ThawTicket).is_some(), thawable, "{:?}", status);
+    }
+
+    assert_eq!(
+      run(LotteryStatus::Open, &[LotteryAction::TransferTicket, LotteryAction::Cancel, LotteryAction::ThawTicket]),
+      Some(LotteryStatus::Cancelled)
+    );
+  }
+
+  #[test]
+  fn soulbound_tickets_only_move_while_sales_are_open() {
+    for status in ALL_STATUSES {
+      assert_eq!(
+        status.next(LotteryAction::TransferTicket).is_some(),
+        status == LotteryStatus::Open,
+        "{:?}",
+        status
+      );
+    }
+
+    assert_eq!(
+      Error::from(LotteryStatus::Closed.rejection(LotteryAction::TransferTicket)),
+      ErrorCode::LotteryNotOpen.into()
+    );
+  }
+
+  #[test]
+  fn royalties_are_swept_only_while_a_prize_is_pending() {
+    for status in ALL_STATUSES {
+      let sweepable = !matches!(status, LotteryStatus::Claimed | LotteryStatus::Cancelled);
+
+      assert_eq!(status.next(LotteryAction::SweepRoyalties).is_some(), sweepable, "{:?}", status);
+    }
+
+    assert_eq!(
+      Error::from(LotteryStatus::Claimed.rejection(LotteryAction::SweepRoyalties)),
+      ErrorCode::PrizeAlreadyClaimed.into()
+    );
+  }
+
+  #[test]
+  fn cancelled_lottery_releases_royalties_but_keeps_refunds() {
+    // 20_000 of ticket sales, 5_000 from a sponsor and 5_000 of swept royalties
+    let mut lottery = TokenLottery {
+      status: LotteryStatus::Cancelled,
+      lottery_pot_amount: 30_000,
+      swept_royalties: 5_000,
+      guaranteed_prize: 10_000,
+      ..Default::default()
+    };
+
+    // 2_000 of royalties arrived after the cancellation
+    assert_eq!(lottery.take_leftover_royalties(42_000).unwrap(), 7_000);
+    assert_eq!(lottery.lottery_pot_amount, 25_000);
+
+    // refunds, sponsorships and the guarantee can still be paid in full
+    lottery.release_from_pot(25_000).unwrap();
+    assert_eq!(lottery.take_leftover_royalties(10_000).unwrap(), 0);
+  }
+
+  #[test]
+  fn claimed_lottery_releases_royalties_from_later_resales() {
+    let mut lottery = TokenLottery { swept_royalties: 3_000, lottery_pot_amount: 20_000, ..drawn_lottery() };
+
+    assert_eq!(
+      lottery.take_leftover_royalties(20_000).unwrap_err(),
+      ErrorCode::PrizeNotClaimed.into()
+    );
+
+    lottery.pay_out_prize().unwrap();
+    assert_eq!(lottery.take_leftover_royalties(1_500).unwrap(), 1_500);
+  }
+
+  #[test]
+  fn tickets_need_a_collection_and_open_sales() {
+    assert_eq!(LotteryStatus::Created.next(LotteryAction::SellTicket), None);
+    assert_eq!(LotteryStatus::Closed.next(LotteryAction::SellTicket), None);
+    assert_eq!(LotteryStatus::CollectionReady.next(LotteryAction::CommitRandomness), None);
+    assert_eq!(LotteryStatus::CollectionReady.next(LotteryAction::InitializeCollection), None);
+  }
+
+  #[test]
+  fn rejections_name_the_blocking_state() {
+    let cases = [
+      (LotteryStatus::Cancelled, LotteryAction::SellTicket, ErrorCode::LotteryCancelled),
+      (LotteryStatus::Open, LotteryAction::Refund, ErrorCode::LotteryNotCancelled),
+      (LotteryStatus::Created, LotteryAction::SellTicket, ErrorCode::CollectionNotInitialized),
+      (LotteryStatus::Claimed, LotteryAction::ClaimPrize, ErrorCode::PrizeAlreadyClaimed),
+      (LotteryStatus::WinnerChosen, LotteryAction::ThawTicket, ErrorCode::PrizeNotClaimed),
+      (LotteryStatus::WinnerChosen, LotteryAction::Cancel, ErrorCode::WinnerChosen),
+      (LotteryStatus::Closed, LotteryAction::ClaimPrize, ErrorCode::WinnerNotChosen),
+      (LotteryStatus::RandomnessCommitted, LotteryAction::Cancel, ErrorCode::DrawInProgress),
+      (LotteryStatus::Open, LotteryAction::Configure, ErrorCode::ConfigLocked),
+      (LotteryStatus::Closed, LotteryAction::SellTicket, ErrorCode::LotteryNotOpen),
+      (LotteryStatus::Closed, LotteryAction::ChooseWinner, ErrorCode::InvalidStatusTransition),
+    ];
+
+    for (status, action, error) in cases {
+      assert_eq!(status.next(action), None);
+      assert_eq!(Error::from(status.rejection(action)), error.into(), "{:?} {:?}", status, action);
+    }
+  }
+
+  #[test]
+  fn sales_close_once_the_window_ends() {
+    let mut lottery = TokenLottery { end_time: 200, status: LotteryStatus::Open, ..Default::default() };
+
+    lottery.close_sales_if_ended(200).unwrap();
+    assert_eq!(lottery.status, LotteryStatus::Open);
+
+    lottery.close_sales_if_ended(201).unwrap();
+    assert_eq!(lottery.status, LotteryStatus::Closed);
+
+    let mut lottery = TokenLottery { end_time: 200, status: LotteryStatus::CollectionReady, ..Default::default() };
+
+    lottery.close_sales_if_ended(201).unwrap();
+    assert_eq!(lottery.status, LotteryStatus::CollectionReady);
+  }
+
+  #[test]
+  fn paused_lottery_stops_sales_and_funding() {
+    let lottery = TokenLottery { start_time: 100, end_time: 200, paused: true, ..Default::default() };
+
+    assert_eq!(
+      ticket_price_for_buyer(&lottery, 150, &Pubkey::new_unique(), None, None).unwrap_err(),
+      ErrorCode::LotteryPaused.into()
+    );
+    assert_eq!(lottery.require_accepting_funds(150).unwrap_err(), ErrorCode::LotteryPaused.into());
+
+    let lottery = TokenLottery { paused: false, ..lottery };
+
+    assert!(ticket_price_for_buyer(&lottery, 150, &Pubkey::new_unique(), None, None).is_ok());
+  }
+
+  #[test]
+  fn ticket_refund_excludes_referral_cut() {
+    let record = TicketRecord {
+      ticket_mint: Pubkey::new_unique(),
+      buyer: Pubkey::new_unique(),
+      index: 0,
+      price_paid: 10_000,
+      referral_paid: 250,
+    };
+
+    assert_eq!(record.refundable_amount().unwrap(), 9_750);
+  }
+
+  #[test]
+  fn referral_reward_takes_bps_of_price() {
+    assert_eq!(referral_reward(10_000, 0).unwrap(), 0);
+    assert_eq!(referral_reward(10_000, 250).unwrap(), 250);
+    assert_eq!(referral_reward(10_001, 5_000).unwrap(), 5_000);
+    assert_eq!(referral_reward(u64::MAX, 10_000).unwrap(), u64::MAX);
+  }
+
+  #[test]
+  fn referral_accounts_must_come_together() {
+    let buyer = Pubkey::new_unique();
+    let referrer = Pubkey::new_unique();
+
+    assert_eq!(referred_by(&buyer, None, None).unwrap(), None);
+    assert_eq!(referred_by(&buyer, Some(referrer), Some(referrer)).unwrap(), Some(referrer));
+
+    // earnings account without the referrer, and the other way round
+    assert_eq!(referred_by(&buyer, None, Some(referrer)).unwrap_err(), ErrorCode::MissingReferralAccounts.into());
+    assert_eq!(referred_by(&buyer, Some(referrer), None).unwrap_err(), ErrorCode::MissingReferralAccounts.into());
+
+    assert_eq!(
+      referred_by(&buyer, Some(referrer), Some(Pubkey::new_unique())).unwrap_err(),
+      ErrorCode::IncorrectReferrer.into()
+    );
+    assert_eq!(referred_by(&buyer, Some(buyer), Some(buyer)).unwrap_err(), ErrorCode::SelfReferral.into());
+  }
+
+  #[test]
+  fn time_tiers_step_price_by_slot() {
+    let schedule = PricingSchedule::TimeTiers {
+      tiers: vec![
+        PriceTier { start_slot: 150, price: 12_000 },
+        PriceTier { start_slot: 180, price: 15_000 },
+      ],
+    };
+
+    assert_eq!(schedule.price(10_000, 100, 0).unwrap(), 10_000);
+    assert_eq!(schedule.price(10_000, 150, 0).unwrap(), 12_000);
+    assert_eq!(schedule.price(10_000, 179, 5).unwrap(), 12_000);
+    assert_eq!(schedule.price(10_000, 200, 5).unwrap(), 15_000);
+  }
+
+  #[test]
+  fn curves_rise_with_tickets_sold() {
+    let linear = PricingSchedule::Linear { increment: 500 };
+    assert_eq!(linear.price(10_000, 0, 0).unwrap(), 10_000);
+    assert_eq!(linear.price(10_000, 0, 4).unwrap(), 12_000);
+
+    let exponential = PricingSchedule::Exponential { growth_bps: 1_000 };
+    assert_eq!(exponential.price(10_000, 0, 0).unwrap(), 10_000);
+    assert_eq!(exponential.price(10_000, 0, 1).unwrap(), 11_000);
+    assert_eq!(exponential.price(10_000, 0, 2).unwrap(), 12_100);
+    assert_eq!(exponential.price(10_000, 0, 3).unwrap(), 13_310);
+  }
+
+  #[test]
+  fn curves_report_overflow() {
+    let linear = PricingSchedule::Linear { increment: u64::MAX };
+    assert_eq!(linear.price(1, 0, 2).unwrap_err(), ErrorCode::MathOverflow.into());
+
+    let exponential = PricingSchedule::Exponential { growth_bps: 10_000 };
+    assert_eq!(exponential.price(1, 0, 63).unwrap(), 1 << 63);
+    assert_eq!(exponential.price(1, 0, 64).unwrap_err(), ErrorCode::MathOverflow.into());
+  }
+
+  #[test]
+  fn time_tiers_must_be_sorted_and_bounded() {
+    let tier = |start_slot| PriceTier { start_slot, price: 1 };
+
+    assert!(PricingSchedule::TimeTiers { tiers: vec![tier(1), tier(2)] }.validate().is_ok());
+    assert!(PricingSchedule::TimeTiers { tiers: vec![] }.validate().is_err());
+    assert!(PricingSchedule::TimeTiers { tiers: vec![tier(2), tier(2)] }.validate().is_err());
+    assert!(PricingSchedule::TimeTiers { tiers: (0..9).map(tier).collect() }.validate().is_err());
+  }
+
+  #[test]
+  fn time_tiers_cannot_give_tickets_away() {
+    let tiers = vec![PriceTier { start_slot: 1, price: 5_000 }, PriceTier { start_slot: 2, price: 0 }];
+
+    assert_eq!(
+      PricingSchedule::TimeTiers { tiers }.validate().unwrap_err(),
+      ErrorCode::InvalidTicketPrice.into()
+    );
+  }
+
+  #[test]
+  fn verify_merkle_proof_accepts_every_listed_wallet() {
+    let wallets = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let (root, proofs) = allowlist(&wallets);
+
+    for (wallet, proof) in wallets.iter().zip(proofs.iter()) {
+      assert!(verify_merkle_proof(proof, root, keccak::hash(wallet.as_ref()).to_bytes()));
+    }
+
+    let outsider = keccak::hash(Pubkey::new_unique().as_ref()).to_bytes();
+    assert!(!verify_merkle_proof(&proofs[0], root, outsider));
+  }
+
+  #[test]
+  fn presale_buyer_pays_presale_price_up_to_the_wallet_limit() {
+    let wallets = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let (root, proofs) = allowlist(&wallets);
+    let lottery = presale_lottery(root);
+    let mut receipt = PresaleReceipt { buyer: Pubkey::default(), tickets_bought: 0 };
+
+    for _ in 0..2 {
+      let price = ticket_price_for_buyer(&lottery, 60, &wallets[2], Some(&proofs[2]), Some(&mut receipt)).unwrap();
+      assert_eq!(price, 5_000);
+    }
+
+    assert_eq!(receipt.buyer, wallets[2]);
+    assert_eq!(receipt.tickets_bought, 2);
+
+    let err = ticket_price_for_buyer(&lottery, 60, &wallets[2], Some(&proofs[2]), Some(&mut receipt)).unwrap_err();
+    assert_eq!(err, ErrorCode::PresaleLimitReached.into());
+  }
+
+  #[test]
+  fn presale_config_is_validated() {
+    let mut lottery = TokenLottery { start_time: 100, ..Default::default() };
+    let root = [7; 32];
+
+    assert_eq!(lottery.configure_presale(50, root, 0, 2).unwrap_err(), ErrorCode::InvalidTicketPrice.into());
+    assert_eq!(lottery.configure_presale(100, root, 5_000, 2).unwrap_err(), ErrorCode::InvalidPresaleConfig.into());
+    assert_eq!(lottery.configure_presale(50, root, 5_000, 0).unwrap_err(), ErrorCode::InvalidPresaleConfig.into());
+    assert_eq!(lottery.presale_merkle_root, [0; 32]);
+
+    lottery.configure_presale(50, root, 5_000, 2).unwrap();
+    assert_eq!(lottery.presale_ticket_price, 5_000);
+    assert!(lottery.in_presale(50));
+  }
+
+  #[test]
+  fn presale_rejects_wallets_without_a_valid_proof() {
+    let wallets = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let (root, proofs) = allowlist(&wallets);
+    let lottery = presale_lottery(root);
+    let mut receipt = PresaleReceipt { buyer: Pubkey::default(), tickets_bought: 0 };
+
+    let err = ticket_price_for_buyer(&lottery, 60, &Pubkey::new_unique(), Some(&proofs[0]), Some(&mut receipt)).unwrap_err();
+    assert_eq!(err, ErrorCode::NotAllowlisted.into());
+
+    let err = ticket_price_for_buyer(&lottery, 60, &wallets[0], None, Some(&mut receipt)).unwrap_err();
+    assert_eq!(err, ErrorCode::NotAllowlisted.into());
+
+    let err = ticket_price_for_buyer(&lottery, 60, &wallets[0], Some(&proofs[0]), None).unwrap_err();
+    assert_eq!(err, ErrorCode::NotAllowlisted.into());
+  }
+
+  #[test]
+  fn regular_sale_ignores_the_allowlist() {
+    let (root, _) = allowlist(&[Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()]);
+    let lottery = presale_lottery(root);
+
+    assert_eq!(ticket_price_for_buyer(&lottery, 100, &Pubkey::new_unique(), None, None).unwrap(), 10_000);
+
+    let err = ticket_price_for_buyer(&lottery, 49, &Pubkey::new_unique(), None, None).unwrap_err();
+    assert_eq!(err, ErrorCode::LotteryNotOpen.into());
+
+    let err = ticket_price_for_buyer(&lottery, 201, &Pubkey::new_unique(), None, None).unwrap_err();
+    assert_eq!(err, ErrorCode::LotteryNotOpen.into());
+
+    let lottery = presale_lottery([0; 32]);
+    let err = ticket_price_for_buyer(&lottery, 60, &Pubkey::new_unique(), None, None).unwrap_err();
+    assert_eq!(err, ErrorCode::LotteryNotOpen.into());
+  }
+
+  #[test]
+  fn ticket_creators_split_royalties_between_treasury_and_pot() {
+    let treasury = Pubkey::new_unique();
+    let pot = Pubkey::new_unique();
+    let lottery = TokenLottery {
+      authority: Pubkey::new_unique(),
+      seller_fee_basis_points: 500,
+      pot_royalty_share: 30,
+      ..Default::default()
+    };
+
+    let creators = ticket_creators(&lottery, pot, treasury).unwrap();
+
+    assert_eq!(creators.len(), 2);
+    assert_eq!((creators[0].address, creators[0].share), (treasury, 70));
+    assert_eq!((creators[1].address, creators[1].share), (pot, 30));
+  }
+
+  #[test]
+  fn ticket_creators_skip_zero_shares_and_royalty_free_tickets() {
+    let pot = Pubkey::new_unique();
+    let lottery = TokenLottery { seller_fee_basis_points: 500, pot_royalty_share: 100, ..Default::default() };
+
+    let creators = ticket_creators(&lottery, pot, Pubkey::new_unique()).unwrap();
+
+    assert_eq!(creators.len(), 1);
+    assert_eq!((creators[0].address, creators[0].share), (pot, 100));
+
+    let lottery = TokenLottery { seller_fee_basis_points: 0, ..lottery };
+
+    assert!(ticket_creators(&lottery, pot, Pubkey::new_unique()).is_none());
+  }
+
+  #[test]
+  fn ticket_name_appends_the_index() {
+    assert_eq!(ticket_name("Token Lottery Ticket #", 7), "Token Lottery Ticket #7");
+  }
+
+  #[test]
+  fn ticket_name_fits_the_metaplex_limit() {
+    let name = ticket_name("Token Lottery Ticket #", 12_345_678_901);
+
+    assert_eq!(name, "Token Lottery Ticket 12345678901");
+    assert!(name.len() <= MAX_NAME_LENGTH);
+    assert_eq!(ticket_name(&"x".repeat(MAX_NAME_LENGTH), u64::MAX).len(), MAX_NAME_LENGTH);
+  }
+
+  #[test]
+  fn ticket_name_truncates_on_a_char_boundary() {
+    let name = ticket_name("Lottery Ticket \u{1F39F}\u{1F39F}\u{1F39F}", 1_000_000);
+
+    assert!(name.len() <= MAX_NAME_LENGTH);
+    assert!(name.ends_with("1000000"));
+  }
+
+  #[test]
+  fn ticket_uris_include_the_ticket_index() {
+    assert_eq!(ticket_uri("https://example.com/lottery", 0), "https://example.com/lottery/0.json");
+    assert_eq!(winning_ticket_uri("https://example.com/lottery", 42), "https://example.com/lottery/42-winner.json");
+  }
+
+  #[test]
+  fn ticket_uri_suffix_fits_the_largest_index() {
+    let base = "b".repeat(MAX_URI_LENGTH - MAX_TICKET_URI_SUFFIX_LENGTH);
+
+    assert_eq!(winning_ticket_uri(&base, u64::MAX).len(), MAX_URI_LENGTH);
+    assert!(ticket_uri(&base, u64::MAX).len() <= MAX_URI_LENGTH);
+  }
+
+  #[test]
+  fn winning_ticket_index_stays_in_range() {
+    let lottery = TokenLottery { total_tickets: 7, ..Default::default() };
+
+    assert_eq!(lottery.winning_ticket_index(0).unwrap(), 0);
+    assert_eq!(lottery.winning_ticket_index(u8::MAX).unwrap(), 255 % 7);
+
+    let lottery = TokenLottery { total_tickets: u64::MAX, ..Default::default() };
+
+    assert_eq!(lottery.winning_ticket_index(u8::MAX).unwrap(), 255);
+  }
+
+  #[test]
+  fn winning_ticket_index_rejects_empty_lottery() {
+    let lottery = TokenLottery::default();
+
+    let err = lottery.winning_ticket_index(42).unwrap_err();
+
+    assert_eq!(err, ErrorCode::MathOverflow.into());
+  }
+}
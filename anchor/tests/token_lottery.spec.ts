@@ -2,7 +2,7 @@ import * as anchor from '@coral-xyz/anchor';
 import * as sb from "@switchboard-xyz/on-demand";
 import { Program } from '@coral-xyz/anchor';
 import { TokenLottery } from '../target/types/token_lottery';
-import { TOKEN_PROGRAM_ID } from '@solana/spl-token';
+import { TOKEN_PROGRAM_ID, createAssociatedTokenAccount } from '@solana/spl-token';
 import SwitchboardIDL from '../switchboard.json';
 
 describe('token_lottery', () => {
@@ -17,8 +17,34 @@ describe('token_lottery', () => {
   const switchboardProgram = new anchor.Program(SwitchboardIDL as anchor.Idl, provider);
   const rngKp = anchor.web3.Keypair.generate();
 
+  const [tokenLotteryAddress] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("token_lottery")],
+    program.programId
+  );
+
+  const TOKEN_METADATA_PROGRAM_ID = new anchor.web3.PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+  function ticketMintAddress(index: anchor.BN) {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [index.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  }
+
+  function metadataAddress(mint: anchor.web3.PublicKey) {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+      TOKEN_METADATA_PROGRAM_ID
+    )[0];
+  }
+
+  async function winningTicketMetadata() {
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    return metadataAddress(ticketMintAddress(tokenLottery.winner));
+  }
+
   async function buyTicket() {
-    const buyTicketIx = await program.methods.buyTicket()
+    const buyTicketIx = await program.methods.buyTicket(null)
       .accounts({
         tokenProgram: TOKEN_PROGRAM_ID,
 
@@ -72,7 +98,8 @@ describe('token_lottery', () => {
     const initConfigIx = await program.methods.initializeConfig(
       new anchor.BN(0),
       new anchor.BN(slot + 11),
-      new anchor.BN(10000)
+      new anchor.BN(10000),
+      new anchor.BN(0)
     ).instruction();
 
     const blockhashContext = await connection.getLatestBlockhash();
@@ -96,7 +123,19 @@ describe('token_lottery', () => {
     console.log("Transaction initConfig signature:", signature);
 
 
-    const initLotteryIx = await program.methods.initializeLottery().accounts({
+    const initLotteryIx = await program.methods.initializeLottery(
+      "Token Lottery Ticket #",
+      "TLT",
+      "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/metadata.json",
+      "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/tickets",
+      {
+        sellerFeeBasisPoints: 0,
+        potRoyaltyShare: 0,
+        ticketStandard: { nonFungible: {} },
+        ruleSet: null,
+        freezeAfterClose: false,
+      }
+    ).accounts({
       tokenProgram: TOKEN_PROGRAM_ID
     }).instruction();
 
@@ -173,6 +212,12 @@ describe('token_lottery', () => {
 
     console.log('Create randomness account signature:', createRandomnessSig);
 
+    // randomness can only be committed once sales have closed
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    while (await connection.getSlot() <= tokenLottery.endTime.toNumber()) {
+      await new Promise((resolve) => setTimeout(resolve, 400));
+    }
+
     const sbCommitIx = await randomness.commitIx(queue);
 
     const commitIx = await program.methods.commitRandomness().accounts( 
@@ -228,9 +273,84 @@ describe('token_lottery', () => {
 
   }, 250000); 
 
+  it("should mark the winning ticket metadata", async () => {
+    const updateIx = await program.methods.updateTicketStatus()
+      .accountsPartial({
+        ticketMetadata: await winningTicketMetadata(),
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+
+    const blockhashContext = await connection.getLatestBlockhash();
+
+    const updateTx = new anchor.web3.Transaction({
+      blockhash: blockhashContext.blockhash,
+      lastValidBlockHeight: blockhashContext.lastValidBlockHeight,
+      feePayer: wallet.payer.publicKey,
+    }).add(updateIx);
+
+    const updateSignature = await anchor.web3.sendAndConfirmTransaction(connection, updateTx, [wallet.payer]);
+    console.log("Update ticket status signature:", updateSignature);
+
+  });
+
+  it("should reject a claim for a ticket that did not win", async () => {
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    const losingIndex = tokenLottery.winner.addn(1).mod(tokenLottery.totalTickets);
+
+    await expect(
+      program.methods.claimPrize()
+        .accountsPartial({
+          ticketMint: ticketMintAddress(losingIndex),
+          ticketMetadata: metadataAddress(ticketMintAddress(losingIndex)),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc()
+    ).rejects.toThrow("ConstraintSeeds");
+  });
+
+  it("should reject a claim against the wrong collection", async () => {
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+
+    await expect(
+      program.methods.claimPrize()
+        .accountsPartial({
+          collectionMint: ticketMintAddress(tokenLottery.winner),
+          ticketMetadata: await winningTicketMetadata(),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc()
+    ).rejects.toThrow("ConstraintSeeds");
+  });
+
+  it("should reject a claim from a wallet without the winning ticket", async () => {
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    const stranger = anchor.web3.Keypair.generate();
+
+    // an empty token account for the winning mint
+    await createAssociatedTokenAccount(
+      connection,
+      wallet.payer,
+      ticketMintAddress(tokenLottery.winner),
+      stranger.publicKey
+    );
+
+    await expect(
+      program.methods.claimPrize()
+        .accountsPartial({
+          payer: stranger.publicKey,
+          ticketMetadata: await winningTicketMetadata(),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([stranger])
+        .rpc()
+    ).rejects.toThrow("NoTicket");
+  });
+
   it("should claim a prize", async () => {
     const claimIx = await program.methods.claimPrize()
-      .accounts({
+      .accountsPartial({
+        ticketMetadata: await winningTicketMetadata(),
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .instruction();
@@ -248,4 +368,37 @@ describe('token_lottery', () => {
 
   });
 
+  it("should hand authority over in two steps", async () => {
+    const newAuthority = anchor.web3.Keypair.generate();
+
+    await program.methods.proposeAuthority(newAuthority.publicKey).rpc();
+
+    await expect(
+      program.methods.acceptAuthority()
+        .accounts({ newAuthority: wallet.publicKey })
+        .rpc()
+    ).rejects.toThrow("NotAuthorized");
+
+    await program.methods.acceptAuthority()
+      .accounts({ newAuthority: newAuthority.publicKey })
+      .signers([newAuthority])
+      .rpc();
+
+    let tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(tokenLottery.authority.equals(newAuthority.publicKey)).toBe(true);
+    expect(tokenLottery.pendingAuthority).toBeNull();
+
+    // hand it back so the wallet stays in control
+    await program.methods.proposeAuthority(wallet.publicKey)
+      .accounts({ authority: newAuthority.publicKey })
+      .signers([newAuthority])
+      .rpc();
+    await program.methods.acceptAuthority()
+      .accounts({ newAuthority: wallet.publicKey })
+      .rpc();
+
+    tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(tokenLottery.authority.equals(wallet.publicKey)).toBe(true);
+  });
+
 });
@@ -0,0 +1,85 @@
+import * as anchor from '@coral-xyz/anchor';
+import { Program } from '@coral-xyz/anchor';
+import { TokenLottery } from '../target/types/token_lottery';
+import V0Authority from './fixtures/v0_authority.json';
+
+// Needs a validator preloaded with a lottery in the unversioned layout, owned by the
+// authority in fixtures/v0_authority.json:
+// setup/start-validator.sh --account 2MQS1jM1eaceAVa2msFsprPmmXDVejV1kNeKUkR24t2w ../anchor/tests/fixtures/v0_token_lottery.json
+// then `anchor deploy` and `anchor run test-migration`.
+describe('token_lottery (v0 migration)', () => {
+  const provider = anchor.AnchorProvider.env();
+  const connection = provider.connection;
+  const wallet = provider.wallet as anchor.Wallet;
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.TokenLottery as Program<TokenLottery>;
+
+  const authority = anchor.web3.Keypair.fromSecretKey(Uint8Array.from(V0Authority));
+  const ticketBaseUri = "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/tickets";
+
+  const [tokenLotteryAddress] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("token_lottery")],
+    program.programId
+  );
+
+  const [rolesAddress] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("roles")],
+    program.programId
+  );
+
+  function migrate(baseUri: string, payer = authority) {
+    return program.methods.migrateLottery(baseUri)
+      .accounts({ payer: payer.publicKey })
+      .signers([payer])
+      .rpc();
+  }
+
+  it('should start from the v0 layout', async () => {
+    const airdrop = await connection.requestAirdrop(authority.publicKey, anchor.web3.LAMPORTS_PER_SOL);
+    await connection.confirmTransaction(airdrop);
+
+    const account = await connection.getAccountInfo(tokenLotteryAddress);
+    expect(account!.data.length).toBe(122);
+    expect(account!.owner.equals(program.programId)).toBe(true);
+  });
+
+  it('should reject a ticket base URI that does not fit ticket metadata', async () => {
+    await expect(migrate(`${ticketBaseUri}/${"a".repeat(200)}`)).rejects.toThrow("MetadataTooLong");
+  });
+
+  it('should only let the v0 authority migrate', async () => {
+    await expect(migrate(ticketBaseUri, wallet.payer)).rejects.toThrow("NotAuthorized");
+  });
+
+  it('should resize the account, top up its rent and set up roles', async () => {
+    const before = await connection.getAccountInfo(tokenLotteryAddress);
+
+    await migrate(`${ticketBaseUri}/`);
+
+    const account = await connection.getAccountInfo(tokenLotteryAddress);
+    expect(account!.data.length).toBe(program.account.tokenLottery.size);
+
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(tokenLottery.version).toBe(1);
+    expect(tokenLottery.status).toEqual({ open: {} });
+    expect(tokenLottery.authority.equals(authority.publicKey)).toBe(true);
+    expect(tokenLottery.totalTickets.toNumber()).toBe(2);
+    expect(tokenLottery.ticketBaseUri).toBe(ticketBaseUri);
+
+    // rent for the larger account is paid on top, the pot stays withdrawable
+    const rent = await connection.getMinimumBalanceForRentExemption(account!.data.length);
+    expect(account!.lamports).toBe(rent + tokenLottery.lotteryPotAmount.toNumber());
+    expect(account!.lamports).toBeGreaterThan(before!.lamports);
+
+    // a v0 lottery had a single key, it keeps every role
+    const roles = await program.account.roles.fetch(rolesAddress);
+    expect(roles.operator.equals(authority.publicKey)).toBe(true);
+    expect(roles.treasury.equals(authority.publicKey)).toBe(true);
+  });
+
+  it('should not migrate twice', async () => {
+    await expect(migrate(ticketBaseUri)).rejects.toThrow();
+  });
+
+});
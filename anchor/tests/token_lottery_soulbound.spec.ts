@@ -0,0 +1,256 @@
+import * as anchor from '@coral-xyz/anchor';
+import * as sb from "@switchboard-xyz/on-demand";
+import { Program } from '@coral-xyz/anchor';
+import { TokenLottery } from '../target/types/token_lottery';
+import { TOKEN_PROGRAM_ID, getAccount, getAssociatedTokenAddressSync, revoke } from '@solana/spl-token';
+import SwitchboardIDL from '../switchboard.json';
+
+// The lottery lives at a fixed PDA, so this suite needs a validator of its own:
+// start one with setup/start-validator.sh, `anchor deploy`, then `anchor run test-soulbound`.
+describe('token_lottery (soulbound tickets)', () => {
+  const provider = anchor.AnchorProvider.env();
+  const connection = provider.connection;
+  const wallet = provider.wallet as anchor.Wallet;
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.TokenLottery as Program<TokenLottery>;
+
+  const switchboardProgram = new anchor.Program(SwitchboardIDL as anchor.Idl, provider);
+  const rngKp = anchor.web3.Keypair.generate();
+  const recipient = anchor.web3.Keypair.generate();
+
+  const [tokenLotteryAddress] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("token_lottery")],
+    program.programId
+  );
+
+  const TOKEN_METADATA_PROGRAM_ID = new anchor.web3.PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+  function ticketMintAddress(index: anchor.BN) {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [index.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  }
+
+  function metadataAddress(mint: anchor.web3.PublicKey) {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+      TOKEN_METADATA_PROGRAM_ID
+    )[0];
+  }
+
+  function ticketAccountAddress(index: anchor.BN, owner = wallet.publicKey) {
+    return getAssociatedTokenAddressSync(ticketMintAddress(index), owner);
+  }
+
+  // the first ticket is handed to `recipient` during the sale
+  function ticketHolder(index: anchor.BN) {
+    return index.isZero() ? recipient : wallet.payer;
+  }
+
+  async function buyTicket() {
+    const buyTicketIx = await program.methods.buyTicket(null)
+      .accounts({
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+
+    const computeIx = anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400000 });
+
+    const blockhashContext = await connection.getLatestBlockhash();
+
+    const buyTicketTx = new anchor.web3.Transaction({
+      feePayer: wallet.payer.publicKey,
+      blockhash: blockhashContext.blockhash,
+      lastValidBlockHeight: blockhashContext.lastValidBlockHeight
+    })
+      .add(buyTicketIx)
+      .add(computeIx);
+
+    const signature = await anchor.web3.sendAndConfirmTransaction(connection, buyTicketTx, [wallet.payer]);
+    console.log("Buy soulbound ticket signature:", signature);
+  }
+
+  function transferTicket(index: anchor.BN) {
+    return program.methods.transferTicket(index)
+      .accountsPartial({
+        holder: wallet.publicKey,
+        recipient: recipient.publicKey,
+        ticketAccount: ticketAccountAddress(index),
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([recipient])
+      .rpc();
+  }
+
+  it('should initialize config and a soulbound lottery', async () => {
+    const slot = await connection.getSlot();
+
+    await program.methods.initializeConfig(
+      new anchor.BN(0),
+      new anchor.BN(slot + 20),
+      new anchor.BN(10000),
+      new anchor.BN(0)
+    ).rpc();
+
+    await program.methods.initializeLottery(
+      "Token Lottery Ticket #",
+      "TLT",
+      "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/metadata.json",
+      "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/tickets",
+      {
+        sellerFeeBasisPoints: 0,
+        potRoyaltyShare: 0,
+        ticketStandard: { nonFungible: {} },
+        ruleSet: null,
+        freezeAfterClose: true,
+      }
+    ).accounts({
+      tokenProgram: TOKEN_PROGRAM_ID
+    }).rpc();
+
+    const airdrop = await connection.requestAirdrop(recipient.publicKey, anchor.web3.LAMPORTS_PER_SOL);
+    await connection.confirmTransaction(airdrop);
+  });
+
+  it("should freeze tickets as they are minted", async () => {
+    await buyTicket();
+    await buyTicket();
+    await buyTicket();
+
+    const ticketAccount = await getAccount(connection, ticketAccountAddress(new anchor.BN(0)));
+    expect(ticketAccount.isFrozen).toBe(true);
+    expect(ticketAccount.delegate?.equals(tokenLotteryAddress)).toBe(true);
+  });
+
+  it("should not let a holder revoke the lottery's delegate", async () => {
+    await expect(
+      revoke(connection, wallet.payer, ticketAccountAddress(new anchor.BN(1)), wallet.payer)
+    ).rejects.toThrow();
+  });
+
+  it("should move a ticket through the program while sales are open", async () => {
+    await transferTicket(new anchor.BN(0));
+
+    const sent = await getAccount(connection, ticketAccountAddress(new anchor.BN(0)));
+    expect(Number(sent.amount)).toBe(0);
+
+    const received = await getAccount(connection, ticketAccountAddress(new anchor.BN(0), recipient.publicKey));
+    expect(Number(received.amount)).toBe(1);
+    expect(received.isFrozen).toBe(true);
+    expect(received.delegate?.equals(tokenLotteryAddress)).toBe(true);
+  });
+
+  it("should commit randomness and reveal the winner", async () => {
+    const queue = new anchor.web3.PublicKey("A43DyUGA7s8eXPxqEjJY6EBu1KKbNgfxF8h17VAHn13w");
+    await new sb.Queue(switchboardProgram, queue).loadData();
+
+    const [randomness, createIx] = await sb.Randomness.create(switchboardProgram, rngKp, queue);
+
+    const createRandomnessTx = await sb.asV0Tx({
+      connection,
+      ixs: [createIx],
+      payer: wallet.publicKey,
+      signers: [wallet.payer, rngKp],
+      computeUnitPrice: 75_000,
+      computeUnitLimitMultiple: 1.3,
+    });
+
+    const blockhashContext = await connection.getLatestBlockhashAndContext();
+
+    const createRandomnessSig = await connection.sendTransaction(createRandomnessTx);
+    await connection.confirmTransaction({
+      signature: createRandomnessSig,
+      blockhash: blockhashContext.value.blockhash,
+      lastValidBlockHeight: blockhashContext.value.lastValidBlockHeight
+    });
+
+    // randomness can only be committed once sales have closed
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    while (await connection.getSlot() <= tokenLottery.endTime.toNumber()) {
+      await new Promise((resolve) => setTimeout(resolve, 400));
+    }
+
+    const commitIx = await program.methods.commitRandomness()
+      .accounts({ randomnessAccountData: randomness.pubkey })
+      .instruction();
+
+    const commitTx = await sb.asV0Tx({
+      connection,
+      ixs: [await randomness.commitIx(queue), commitIx],
+      payer: wallet.publicKey,
+      signers: [wallet.payer],
+      computeUnitPrice: 75_000,
+      computeUnitLimitMultiple: 1.3
+    });
+
+    const commitSignature = await connection.sendTransaction(commitTx);
+    await connection.confirmTransaction({
+      signature: commitSignature,
+      blockhash: blockhashContext.value.blockhash,
+      lastValidBlockHeight: blockhashContext.value.lastValidBlockHeight
+    });
+
+    const revealWinnerIx = await program.methods.chooseWinner()
+      .accounts({ randomnessAccountData: randomness.pubkey })
+      .instruction();
+
+    const revealTx = await sb.asV0Tx({
+      connection,
+      ixs: [await randomness.revealIx(), revealWinnerIx],
+      payer: wallet.publicKey,
+      signers: [wallet.payer],
+      computeUnitPrice: 75_000,
+      computeUnitLimitMultiple: 1.3,
+    });
+
+    const revealSignature = await connection.sendTransaction(revealTx);
+    await connection.confirmTransaction({
+      signature: revealSignature,
+      blockhash: blockhashContext.value.blockhash,
+      lastValidBlockHeight: blockhashContext.value.lastValidBlockHeight
+    });
+
+    const drawn = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(drawn.status).toEqual({ winnerChosen: {} });
+  }, 250000);
+
+  it("should not move tickets once sales have closed", async () => {
+    await expect(transferTicket(new anchor.BN(1))).rejects.toThrow("LotteryNotOpen");
+  });
+
+  it("should pay the prize to the holder of the frozen winning ticket", async () => {
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    const holder = ticketHolder(tokenLottery.winner);
+    const balanceBefore = await connection.getBalance(holder.publicKey);
+
+    await program.methods.claimPrize()
+      .accountsPartial({
+        payer: holder.publicKey,
+        ticketMetadata: metadataAddress(ticketMintAddress(tokenLottery.winner)),
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([holder])
+      .rpc();
+
+    const claimed = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(claimed.status).toEqual({ claimed: {} });
+    expect(await connection.getBalance(holder.publicKey)).toBeGreaterThan(balanceBefore);
+  });
+
+  it("should thaw the winning ticket once the prize is claimed", async () => {
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    const ticketAccount = ticketAccountAddress(tokenLottery.winner, ticketHolder(tokenLottery.winner).publicKey);
+
+    await program.methods.thawTicket(tokenLottery.winner)
+      .accountsPartial({
+        ticketAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    expect((await getAccount(connection, ticketAccount)).isFrozen).toBe(false);
+  });
+
+});
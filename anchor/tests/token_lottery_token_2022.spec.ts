@@ -0,0 +1,183 @@
+import * as anchor from '@coral-xyz/anchor';
+import * as sb from "@switchboard-xyz/on-demand";
+import { Program } from '@coral-xyz/anchor';
+import { TokenLottery } from '../target/types/token_lottery';
+import { TOKEN_2022_PROGRAM_ID } from '@solana/spl-token';
+import SwitchboardIDL from '../switchboard.json';
+
+// The lottery lives at a fixed PDA, so this suite needs a validator of its own:
+// start one with setup/start-validator.sh, `anchor deploy`, then `anchor run test-token-2022`.
+describe('token_lottery (Token-2022 tickets)', () => {
+  const provider = anchor.AnchorProvider.env();
+  const connection = provider.connection;
+  const wallet = provider.wallet as anchor.Wallet;
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.TokenLottery as Program<TokenLottery>;
+
+  const switchboardProgram = new anchor.Program(SwitchboardIDL as anchor.Idl, provider);
+  const rngKp = anchor.web3.Keypair.generate();
+
+  const [tokenLotteryAddress] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("token_lottery")],
+    program.programId
+  );
+
+  function ticketMintAddress(index: anchor.BN) {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [index.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  }
+
+  async function buyTicket() {
+    const buyTicketIx = await program.methods.buyTicketToken2022(null)
+      .accounts({
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .instruction();
+
+    const computeIx = anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 300000 });
+
+    const blockhashContext = await connection.getLatestBlockhash();
+
+    const buyTicketTx = new anchor.web3.Transaction({
+      feePayer: wallet.payer.publicKey,
+      blockhash: blockhashContext.blockhash,
+      lastValidBlockHeight: blockhashContext.lastValidBlockHeight
+    })
+      .add(buyTicketIx)
+      .add(computeIx);
+
+    const signature = await anchor.web3.sendAndConfirmTransaction(connection, buyTicketTx, [wallet.payer]);
+    console.log("Buy Token-2022 ticket signature:", signature);
+  }
+
+  it('should initialize config and a Token-2022 lottery', async () => {
+    const slot = await connection.getSlot();
+
+    await program.methods.initializeConfig(
+      new anchor.BN(0),
+      new anchor.BN(slot + 11),
+      new anchor.BN(10000),
+      new anchor.BN(0)
+    ).rpc();
+
+    await program.methods.initializeLotteryToken2022(
+      "Token Lottery Ticket #",
+      "TLT",
+      "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/metadata.json",
+      "https://raw.githubusercontent.com/solana-developers/developer-bootcamp-2024/refs/heads/main/project-9-token-lottery/tickets",
+      {
+        sellerFeeBasisPoints: 0,
+        potRoyaltyShare: 0,
+        ticketStandard: { token2022: {} },
+        ruleSet: null,
+        freezeAfterClose: false,
+      }
+    ).rpc();
+  });
+
+  it("should buy Token-2022 tickets", async () => {
+    await buyTicket();
+    await buyTicket();
+    await buyTicket();
+
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(tokenLottery.totalTickets.toNumber()).toBe(3);
+  });
+
+  it("should commit randomness and reveal the winner", async () => {
+    const queue = new anchor.web3.PublicKey("A43DyUGA7s8eXPxqEjJY6EBu1KKbNgfxF8h17VAHn13w");
+    await new sb.Queue(switchboardProgram, queue).loadData();
+
+    const [randomness, createIx] = await sb.Randomness.create(switchboardProgram, rngKp, queue);
+
+    const createRandomnessTx = await sb.asV0Tx({
+      connection,
+      ixs: [createIx],
+      payer: wallet.publicKey,
+      signers: [wallet.payer, rngKp],
+      computeUnitPrice: 75_000,
+      computeUnitLimitMultiple: 1.3,
+    });
+
+    const blockhashContext = await connection.getLatestBlockhashAndContext();
+
+    const createRandomnessSig = await connection.sendTransaction(createRandomnessTx);
+    await connection.confirmTransaction({
+      signature: createRandomnessSig,
+      blockhash: blockhashContext.value.blockhash,
+      lastValidBlockHeight: blockhashContext.value.lastValidBlockHeight
+    });
+
+    // randomness can only be committed once sales have closed
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    while (await connection.getSlot() <= tokenLottery.endTime.toNumber()) {
+      await new Promise((resolve) => setTimeout(resolve, 400));
+    }
+
+    const commitIx = await program.methods.commitRandomness()
+      .accounts({ randomnessAccountData: randomness.pubkey })
+      .instruction();
+
+    const commitTx = await sb.asV0Tx({
+      connection,
+      ixs: [await randomness.commitIx(queue), commitIx],
+      payer: wallet.publicKey,
+      signers: [wallet.payer],
+      computeUnitPrice: 75_000,
+      computeUnitLimitMultiple: 1.3
+    });
+
+    const commitSignature = await connection.sendTransaction(commitTx);
+    await connection.confirmTransaction({
+      signature: commitSignature,
+      blockhash: blockhashContext.value.blockhash,
+      lastValidBlockHeight: blockhashContext.value.lastValidBlockHeight
+    });
+
+    const revealWinnerIx = await program.methods.chooseWinner()
+      .accounts({ randomnessAccountData: randomness.pubkey })
+      .instruction();
+
+    const revealTx = await sb.asV0Tx({
+      connection,
+      ixs: [await randomness.revealIx(), revealWinnerIx],
+      payer: wallet.publicKey,
+      signers: [wallet.payer],
+      computeUnitPrice: 75_000,
+      computeUnitLimitMultiple: 1.3,
+    });
+
+    const revealSignature = await connection.sendTransaction(revealTx);
+    await connection.confirmTransaction({
+      signature: revealSignature,
+      blockhash: blockhashContext.value.blockhash,
+      lastValidBlockHeight: blockhashContext.value.lastValidBlockHeight
+    });
+
+    const drawn = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(drawn.status).toEqual({ winnerChosen: {} });
+  }, 250000);
+
+  it("should claim the prize with a Token-2022 ticket", async () => {
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    const balanceBefore = await connection.getBalance(wallet.publicKey);
+
+    // Token-2022 tickets carry no Metaplex metadata
+    await program.methods.claimPrize()
+      .accountsPartial({
+        ticketMint: ticketMintAddress(tokenLottery.winner),
+        ticketMetadata: null,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .rpc();
+
+    const claimed = await program.account.tokenLottery.fetch(tokenLotteryAddress);
+    expect(claimed.status).toEqual({ claimed: {} });
+    expect(claimed.lotteryPotAmount.toNumber()).toBe(0);
+    expect(await connection.getBalance(wallet.publicKey)).toBeGreaterThan(balanceBefore);
+  });
+
+});
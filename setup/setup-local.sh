@@ -10,4 +10,6 @@ solana account -u m --output json-compact --output-file randomness_queue.json A4
 solana account -u m --output json-compact --output-file sb_randomness_config.json 7Gs9n5FQMeC9XcEhg281bRZ6VHRrCvqp5Yq1j78HkvNa
 solana program dump -u m SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv ondemand.so
 solana program dump -u m SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f switchboard.so
-solana program dump -u m metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s metadata.so
\ No newline at end of file
+solana program dump -u m metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s metadata.so
+solana program dump -u m auth9SigNpDKz4sJJ1DfCTuZrZNSAgh9sFD3rboVmgg auth_rules.so
+solana account -u m --output json-compact --output-file rule_set.json eBJLFYPxJmMGKuFwpDWkzxZeUrad92kZRC5BJLpzyT9
\ No newline at end of file
@@ -1 +1 @@
-solana-test-validator --account 3DNK48NH6jvay2nHBiW3wk5yWegD9C2crk2vd9aznRz6 oracle7.json --account 7EyXLrFUtoRoYKhPBnRpjyo2nGTsfGgo2d7XcPb4TwPF oracle6.json --account 2RN1v42zWzzKhLty3Dgen1vbRc4eBsE8PCHanvaSLwJc oracle5.json --account CXyurDdbo9JR5Xh9QuknMJSsuGM3aQdsa38ZVrKSjp1c oracle4.json --account GLc9EQ5ARgnBJvM59wU6eNjaeAEeBa1Gj7jp8rT5NJ8v oracle3.json --account 8Vjo4QEbmB9QhhBu6QiTy66G1tw8WomtFVWECMi3a71y oracle2.json --account BuZBFufhjGn1HDUCukJYognbeoQQW8ACZJq5sWoQPnGe oracle1.json --account GcNZRMqGSEyEULZnLDD3ParcHTgFBrNfUdUCDtThP55e oracle0.json --bpf-program metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s metadata.so --bpf-program SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f switchboard.so --account A43DyUGA7s8eXPxqEjJY6EBu1KKbNgfxF8h17VAHn13w randomness_queue.json --bpf-program SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv ondemand.so --account 7Gs9n5FQMeC9XcEhg281bRZ6VHRrCvqp5Yq1j78HkvNa sb_randomness_config.json -r
\ No newline at end of file
+solana-test-validator --account 3DNK48NH6jvay2nHBiW3wk5yWegD9C2crk2vd9aznRz6 oracle7.json --account 7EyXLrFUtoRoYKhPBnRpjyo2nGTsfGgo2d7XcPb4TwPF oracle6.json --account 2RN1v42zWzzKhLty3Dgen1vbRc4eBsE8PCHanvaSLwJc oracle5.json --account CXyurDdbo9JR5Xh9QuknMJSsuGM3aQdsa38ZVrKSjp1c oracle4.json --account GLc9EQ5ARgnBJvM59wU6eNjaeAEeBa1Gj7jp8rT5NJ8v oracle3.json --account 8Vjo4QEbmB9QhhBu6QiTy66G1tw8WomtFVWECMi3a71y oracle2.json --account BuZBFufhjGn1HDUCukJYognbeoQQW8ACZJq5sWoQPnGe oracle1.json --account GcNZRMqGSEyEULZnLDD3ParcHTgFBrNfUdUCDtThP55e oracle0.json --bpf-program metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s metadata.so --bpf-program SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f switchboard.so --account A43DyUGA7s8eXPxqEjJY6EBu1KKbNgfxF8h17VAHn13w randomness_queue.json --bpf-program SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv ondemand.so --account 7Gs9n5FQMeC9XcEhg281bRZ6VHRrCvqp5Yq1j78HkvNa sb_randomness_config.json --bpf-program auth9SigNpDKz4sJJ1DfCTuZrZNSAgh9sFD3rboVmgg auth_rules.so --account eBJLFYPxJmMGKuFwpDWkzxZeUrad92kZRC5BJLpzyT9 rule_set.json -r "$@"
\ No newline at end of file